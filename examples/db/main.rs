@@ -16,7 +16,7 @@ fn router(cx: Cx) -> Router {
 async fn main() -> Result<()> {
     // let db_url = dotenv("DATABASE_URL").expect("DATABASE_URL not found");
     let db = db(":memory:").await?;
-    let _ = db.create_todos().await?;
+    db.migrate().await?;
     let cx = Cx { db };
     serve("::1:9001", router(cx)).await;
 
@@ -145,13 +145,15 @@ impl Cx {
 }
 
 db!(
-    let create_todos = r#"
-        create table if not exists todos (
+    let migrate = [
+        r#"
+        create table todos (
             id integer primary key,
             content text unique not null,
             created_at integer not null default(unixepoch())
         )
-    "# as Todo;
+        "# as Todo,
+    ];
 
     let insert_todo = r#"
         insert into todos (content)
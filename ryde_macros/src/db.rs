@@ -1,65 +1,617 @@
 use proc_macro2::{Ident, Span, TokenStream};
 use quote::quote;
 use sqlparser::ast::{
-    AlterTableOperation, Assignment, OnConflict, OnConflictAction, OnInsert, Query, Select,
-    SelectItem, SetExpr, TableFactor, TableWithJoins,
+    AlterTableOperation, Assignment, JoinConstraint, JoinOperator, ObjectType, OnConflict,
+    OnConflictAction, OnInsert, Query, Select, SelectItem, SetExpr, TableConstraint, TableFactor,
+    TableWithJoins, ViewColumnDef,
 };
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Location, Token, Tokenizer};
 use sqlparser::{ast::Statement, dialect::SQLiteDialect, parser::Parser};
-use std::collections::HashSet;
-use syn::{ExprCast, LitStr, LocalInit, PatIdent, Result};
+use syn::{ExprArray, ExprCast, LitStr, LocalInit, PatIdent, Result};
 
 pub fn db_macro(exprs: Vec<SqlExpr>) -> Result<TokenStream> {
-    let input = to_input(exprs);
-    let output = to_output(input);
+    let input = to_input(exprs)?;
+    let param_counts = validate(&input.defs)?;
+    let output = to_output(input)?;
+    check_param_counts(&output, &param_counts)?;
     let source = to_tokens(output);
 
     Ok(source)
 }
 
-fn to_input(exprs: Vec<SqlExpr>) -> Input {
-    let defs = exprs
-        .into_iter()
-        .filter_map(to_statement_expr)
+fn to_input(exprs: Vec<SqlExpr>) -> Result<Input> {
+    let mut defs = combine_errors(exprs.into_iter().map(to_statement_expr))?;
+    let columns = schema(&defs);
+    let crud = crud_defs(&defs, &columns)?;
+    defs.extend(combine_errors(crud.into_iter().map(to_statement_expr))?);
+    let relations = relation_defs(&defs, &columns);
+    defs.extend(combine_errors(
+        relations.into_iter().map(to_statement_expr),
+    )?);
+
+    Ok(Input { defs, columns })
+}
+
+// #[crud] on a create table adds insert, by id, all, update and delete queries
+// unless a query with the same name is already defined, all_notes appends an s to the
+// struct name and #[crud(plural = "categories")] spells it out instead
+fn crud_defs(defs: &[SqlExpr], columns: &[Column]) -> Result<Vec<SqlExpr>> {
+    let mut crud = vec![];
+    for def in defs.iter().filter(|def| def.crud) {
+        let struct_ident = match &def.cast {
+            Cast::T(ident) | Cast::Vec(ident) => ident,
+            Cast::None => {
+                return Err(syn::Error::new(
+                    def.span,
+                    format!("{}: #[crud] needs a struct name like `as Post`", def.ident),
+                ))
+            }
+        };
+        let Some(table) = def.statements.iter().find_map(|stmt| match stmt {
+            Statement::CreateTable { name, .. } => Some(name.to_string()),
+            _ => None,
+        }) else {
+            return Err(syn::Error::new(
+                def.span,
+                format!("{}: #[crud] only works on create table", def.ident),
+            ));
+        };
+        let table_columns = columns
+            .iter()
+            .filter(|c| c.table_name == table)
+            .collect::<Vec<_>>();
+        let primary_key = table_columns
+            .iter()
+            .filter(|c| c.primary_key)
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        // only a single integer primary key is a rowid alias that sqlite fills in
+        let rowid = match &primary_key[..] {
+            [pk] => table_columns
+                .iter()
+                .find(|c| c.name == *pk && c.data_type.inner() == &DataType::Integer),
+            _ => None,
+        };
+        let insert_columns = table_columns
+            .iter()
+            .filter(|c| Some(*c) != rowid)
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        let key = primary_key
+            .iter()
+            .map(|pk| format!("{} = ?", pk))
+            .collect::<Vec<_>>()
+            .join(" and ");
+        let name = pascal_to_snake(&struct_ident.to_string());
+        let mut sqls = vec![
+            (
+                format!("insert_{}", name),
+                format!(
+                    "insert into {} ({}) values ({}) returning *",
+                    table,
+                    insert_columns.join(", "),
+                    vec!["?"; insert_columns.len()].join(", ")
+                ),
+                Cast::T(struct_ident.clone()),
+            ),
+            (
+                format!("all_{}", plural(def, &name)),
+                match primary_key.is_empty() {
+                    true => format!("select * from {}", table),
+                    false => format!(
+                        "select * from {} order by {}",
+                        table,
+                        primary_key.join(", ")
+                    ),
+                },
+                Cast::Vec(struct_ident.clone()),
+            ),
+        ];
+        if !primary_key.is_empty() {
+            let assignments = table_columns
+                .iter()
+                .filter(|c| !c.primary_key)
+                .map(|c| format!("{} = ?", c.name))
+                .collect::<Vec<_>>();
+            sqls.push((
+                format!("{}_by_id", name),
+                format!("select * from {} where {} limit 1", table, key),
+                Cast::T(struct_ident.clone()),
+            ));
+            if !assignments.is_empty() {
+                sqls.push((
+                    format!("update_{}", name),
+                    format!(
+                        "update {} set {} where {} returning *",
+                        table,
+                        assignments.join(", "),
+                        key
+                    ),
+                    Cast::T(struct_ident.clone()),
+                ));
+            }
+            sqls.push((
+                format!("delete_{}", name),
+                format!("delete from {} where {} returning *", table, key),
+                Cast::T(struct_ident.clone()),
+            ));
+        }
+        crud.extend(
+            sqls.into_iter()
+                .filter(|(ident, ..)| !defs.iter().any(|def| def.ident == ident))
+                .map(|(ident, sql, cast)| SqlExpr {
+                    ident: Ident::new(&ident, def.ident.span()),
+                    sql,
+                    span: def.span,
+                    statements: vec![],
+                    cast,
+                    migration: false,
+                    types: vec![],
+                    crud: false,
+                    triggers: false,
+                    functions: vec![],
+                    relation: None,
+                    plural: None,
+                }),
+        );
+    }
+
+    Ok(crud)
+}
+
+// foreign keys between tables with structs add helpers both ways, like
+// likes_for_post(post_id), likes_for_posts(&[Post]) and post_for_like(&Like),
+// the plurals come from #[crud(plural = "...")] when a table has one
+fn relation_defs(defs: &[SqlExpr], columns: &[Column]) -> Vec<SqlExpr> {
+    let structs = defs
+        .iter()
+        .filter_map(|def| {
+            let struct_ident = match &def.cast {
+                Cast::T(ident) | Cast::Vec(ident) => ident,
+                Cast::None => return None,
+            };
+            def.statements.iter().find_map(|stmt| match stmt {
+                Statement::CreateTable { name, .. } => Some((name.to_string(), struct_ident, def)),
+                _ => None,
+            })
+        })
         .collect::<Vec<_>>();
-    let columns = defs.iter().flat_map(columns).collect::<HashSet<Column>>();
+    let table_struct = |table: &str| structs.iter().find(|(name, ..)| name == table);
+    let primary_key = |table: &str| {
+        columns
+            .iter()
+            .find(|c| c.table_name == table && c.primary_key)
+    };
+    let mut relations = vec![];
+    for column in columns {
+        let Some((foreign_table, foreign_column)) = &column.references else {
+            continue;
+        };
+        let (Some((table, child, def)), Some((_, parent, parent_def))) = (
+            table_struct(&column.table_name),
+            table_struct(foreign_table),
+        ) else {
+            continue;
+        };
+        let Some(foreign_column) = foreign_column
+            .clone()
+            .or_else(|| primary_key(foreign_table).map(|c| c.name.clone()))
+        else {
+            continue;
+        };
+        let child_name = pascal_to_snake(&child.to_string());
+        let children = plural(def, &child_name);
+        let parent_name = pascal_to_snake(&parent.to_string());
+        // author_id references users reads as posts_for_author
+        let role = match column.name.strip_suffix("_id") {
+            Some(role) if !role.is_empty() => role.to_string(),
+            _ => parent_name.clone(),
+        };
+        let roles = match role == parent_name {
+            true => plural(parent_def, &role),
+            false => format!("{}s", role),
+        };
+        let order_by = match primary_key(table) {
+            Some(pk) => format!(" order by {}", pk.name),
+            None => "".into(),
+        };
+        let sqls = [
+            (
+                format!("{}_for_{}", children, role),
+                format!(
+                    "select * from {} where {} = ?{}",
+                    table, column.name, order_by
+                ),
+                Cast::Vec((*child).clone()),
+                None,
+            ),
+            (
+                format!("{}_for_{}", children, roles),
+                format!(
+                    "select * from {} where {} in (?){}",
+                    table, column.name, order_by
+                ),
+                Cast::Vec((*child).clone()),
+                Some(Column {
+                    name: roles.clone(),
+                    arg: Some(Arg::Rows((*parent).clone(), foreign_column.clone())),
+                    ..Default::default()
+                }),
+            ),
+            (
+                format!("{}_for_{}", role, child_name),
+                format!(
+                    "select * from {} where {} = ? limit 1",
+                    foreign_table, foreign_column
+                ),
+                Cast::T((*parent).clone()),
+                Some(Column {
+                    name: child_name.clone(),
+                    arg: Some(Arg::Row((*child).clone(), column.name.clone())),
+                    ..Default::default()
+                }),
+            ),
+        ];
+        relations.extend(
+            sqls.into_iter()
+                .filter(|(ident, ..)| !defs.iter().any(|def| def.ident == ident))
+                .map(|(ident, sql, cast, relation)| SqlExpr {
+                    ident: Ident::new(&ident, def.ident.span()),
+                    sql,
+                    span: def.span,
+                    statements: vec![],
+                    cast,
+                    migration: false,
+                    types: vec![],
+                    crud: false,
+                    triggers: false,
+                    functions: vec![],
+                    relation,
+                    plural: None,
+                }),
+        );
+    }
 
-    Input { defs, columns }
+    relations
 }
 
-fn to_output(input: Input) -> Output {
-    let stmts = input
-        .defs
-        .into_iter()
-        .filter_map(|def| to_stmt(&input.columns, def))
-        .collect();
-    Output { stmts }
+// the #[crud(plural = "...")] of a table or its name with an s
+fn plural(def: &SqlExpr, name: &str) -> String {
+    def.plural.clone().unwrap_or_else(|| format!("{}s", name))
+}
+
+fn pascal_to_snake(input: &str) -> String {
+    input
+        .chars()
+        .enumerate()
+        .flat_map(|(i, c)| match c.is_uppercase() && i > 0 {
+            true => vec!['_', c.to_ascii_lowercase()],
+            false => vec![c.to_ascii_lowercase()],
+        })
+        .collect()
+}
+
+// prepares every statement against an in memory database built from the schema and
+// returns how many parameters sqlite counted in each single statement query
+fn validate(defs: &[SqlExpr]) -> Result<Vec<(String, usize, Span)>> {
+    let conn = rusqlite::Connection::open_in_memory()
+        .map_err(|err| syn::Error::new(Span::call_site(), err))?;
+    // functions registered with DbOptions::function only exist at runtime so
+    // #[functions(name)] stands them in with one that takes any arguments
+    for def in defs {
+        for name in &def.functions {
+            conn.create_scalar_function(
+                name.to_string().as_str(),
+                -1,
+                rusqlite::functions::FunctionFlags::SQLITE_UTF8,
+                |_| Ok(rusqlite::types::Null),
+            )
+            .map_err(|err| syn::Error::new(name.span(), err))?;
+        }
+    }
+    let (schema_defs, query_defs): (Vec<_>, Vec<_>) = defs
+        .iter()
+        .partition(|def| def.migration || def.statements.iter().any(is_schema_statement));
+    for def in schema_defs {
+        conn.execute_batch(&def.sql)
+            .map_err(|err| sql_error(def, err))?;
+    }
+    let counts = combine_errors(query_defs.into_iter().map(|def| {
+        match &def.statements[..] {
+            [_] => conn
+                .prepare(&def.sql)
+                .map(|stmt| vec![(def.ident.to_string(), stmt.parameter_count(), def.span)])
+                .map_err(|err| sql_error(def, err)),
+            statements => statements
+                .iter()
+                .map(|statement| {
+                    conn.prepare(&statement.to_string())
+                        .map(|_| vec![])
+                        .map_err(|err| sql_error(def, err))
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(|counts| counts.concat()),
+        }
+    }))?;
+
+    Ok(counts.concat())
+}
+
+// placeholders the analysis missed would leave every call one argument short
+fn check_param_counts(output: &Output, counts: &[(String, usize, Span)]) -> Result<()> {
+    combine_errors(output.stmts.iter().map(|stmt| {
+        let (ident, in_cols) = match stmt {
+            Stmt::Execute { ident, in_cols, .. }
+            | Stmt::AggQuery { ident, in_cols, .. }
+            | Stmt::Query { ident, in_cols, .. } => (ident, in_cols),
+            _ => return Ok(()),
+        };
+        match counts.iter().find(|(name, ..)| ident == name) {
+            Some((_, count, span)) if *count != in_cols.len() => Err(syn::Error::new(
+                *span,
+                format!(
+                    "{}: sqlite expects {} parameters but {} could be typed\nhint: placeholders only work where a column or type gives them one",
+                    ident,
+                    count,
+                    in_cols.len()
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }))?;
+
+    Ok(())
+}
+
+fn is_schema_statement(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::CreateTable { .. }
+            | Statement::AlterTable { .. }
+            | Statement::CreateIndex { .. }
+            | Statement::CreateView { .. }
+            | Statement::CreateVirtualTable { .. }
+            | Statement::Drop { .. }
+    )
+}
+
+// reports every failing statement instead of stopping at the first one
+fn combine_errors<T>(results: impl Iterator<Item = Result<T>>) -> Result<Vec<T>> {
+    let mut values = vec![];
+    let mut error: Option<syn::Error> = None;
+    for result in results {
+        match (result, error.as_mut()) {
+            (Ok(value), _) => values.push(value),
+            (Err(err), Some(error)) => error.combine(err),
+            (Err(err), None) => error = Some(err),
+        }
+    }
+
+    match error {
+        Some(error) => Err(error),
+        None => Ok(values),
+    }
+}
+
+fn sql_error(def: &SqlExpr, err: rusqlite::Error) -> syn::Error {
+    let hint = match err.to_string().starts_with("no such function") {
+        true => {
+            "\nhint: declare functions registered with DbOptions::function like #[functions(name)]"
+        }
+        false => "",
+    };
+    syn::Error::new(def.span, format!("{}: {}{}", def.ident, err, hint))
+}
+
+fn to_output(input: Input) -> Result<Output> {
+    let (migrations, defs): (Vec<_>, Vec<_>) =
+        input.defs.into_iter().partition(|def| def.migration);
+    let many_idents = defs
+        .iter()
+        .map(|def| def.ident.to_string())
+        .collect::<Vec<_>>();
+    let mut stmts = combine_errors(defs.into_iter().map(|def| {
+        let insert = matches!(def.statements.last(), Some(Statement::Insert { .. }));
+        let stmt = to_stmt(&input.columns, def)?;
+        let many = stmt
+            .as_ref()
+            .filter(|_| insert)
+            .and_then(insert_many_stmt)
+            .filter(|many| match many {
+                Stmt::InsertMany { ident, .. } => !many_idents.contains(&ident.to_string()),
+                _ => false,
+            });
+        Ok(stmt.into_iter().chain(many).collect::<Vec<_>>())
+    }))?
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    stmts.extend(migrate_stmt(migrations)?);
+    Ok(Output { stmts })
+}
+
+// every insert also gets a <name>_many that reuses one cached statement in one transaction
+fn insert_many_stmt(stmt: &Stmt) -> Option<Stmt> {
+    let (ident, sql, in_cols, returning) = match stmt {
+        Stmt::Execute {
+            ident,
+            sql,
+            in_cols,
+        } => (ident, sql, in_cols, None),
+        Stmt::Query {
+            cast,
+            ident,
+            sql,
+            in_cols,
+            ..
+        } => {
+            let struct_ident = match cast {
+                Cast::T(ident) | Cast::Vec(ident) => ident.clone(),
+                Cast::None => struct_ident(ident),
+            };
+            (ident, sql, in_cols, Some(struct_ident))
+        }
+        _ => return None,
+    };
+    let types: Vec<TokenStream> = in_cols.iter().map(arg_type_tokens).collect();
+    let row_type = match &types[..] {
+        [] => return None,
+        [ty] => quote!(#ty),
+        _ => quote!((#(#types),*)),
+    };
+    let rows = Column {
+        name: "rows".into(),
+        data_type: DataType::Custom(syn::parse_quote!(Vec<#row_type>)),
+        ..Default::default()
+    };
+
+    Some(Stmt::InsertMany {
+        ident: Ident::new(&format!("{}_many", ident), ident.span()),
+        sql: sql.clone(),
+        in_cols: in_cols.clone(),
+        rows: vec![rows],
+        returning,
+    })
 }
 
 fn to_tokens(output: Output) -> TokenStream {
     let traits: Vec<TokenStream> = output.stmts.iter().map(trait_tokens).collect();
     let impls: Vec<TokenStream> = output.stmts.iter().map(impl_tokens).collect();
+    let tx_impls: Vec<TokenStream> = output.stmts.iter().map(tx_impl_tokens).collect();
+    let db_impls: Vec<TokenStream> = output.stmts.iter().map(db_impl_tokens).collect();
     let tokens: Vec<TokenStream> = output.stmts.into_iter().map(stmt_tokens).collect();
 
     quote! {
         #(#tokens)*
 
         #[derive(Clone)]
-        pub struct Db(tokio_rusqlite::Connection);
+        pub struct Db {
+            writer: tokio_rusqlite::Connection,
+            readers: std::sync::Arc<Vec<tokio_rusqlite::Connection>>,
+            next_reader: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+            changes: ryde::db::Changes,
+        }
 
         pub async fn db(database_url: impl AsRef<std::path::Path>) -> ryde::Result<Db> {
-            let connection = Connection::open(database_url).await?;
-            connection
-                .call(|conn| {
-                    conn.execute_batch(
-                        "PRAGMA foreign_keys = ON;
-                        PRAGMA journal_mode = WAL;
-                        PRAGMA synchronous = NORMAL;",
-                    )
-                    .map_err(|err| err.into())
-                })
-                .await?;
+            db_with(database_url, ryde::db::DbOptions::default()).await
+        }
+
+        pub async fn db_with(
+            database_url: impl AsRef<std::path::Path>,
+            options: ryde::db::DbOptions,
+        ) -> ryde::Result<Db> {
+            let (writer, readers, changes) = options.open(database_url.as_ref()).await?;
+
+            Ok(Db {
+                writer,
+                readers: std::sync::Arc::new(readers),
+                next_reader: Default::default(),
+                changes,
+            })
+        }
+
+        impl Db {
+            pub async fn backup_to(&self, path: impl AsRef<std::path::Path>) -> ryde::Result<()> {
+                ryde::db::backup(&self.writer, path, |_| {}).await
+            }
+
+            pub async fn backup_with_progress(
+                &self,
+                path: impl AsRef<std::path::Path>,
+                progress: impl FnMut(rusqlite::backup::Progress) + Send + 'static,
+            ) -> ryde::Result<()> {
+                ryde::db::backup(&self.writer, path, progress).await
+            }
+
+            pub async fn snapshot(&self, path: impl AsRef<std::path::Path>) -> ryde::Result<()> {
+                ryde::db::snapshot(&self.writer, path).await
+            }
+
+            pub fn backup_every(
+                &self,
+                dir: impl AsRef<std::path::Path>,
+                interval: std::time::Duration,
+                keep: usize,
+            ) -> ryde::tokio::task::JoinHandle<ryde::Result<()>> {
+                ryde::db::backup_every(self.writer.clone(), dir, interval, keep)
+            }
+
+            // inserts, updates and deletes on a table, sent after the call that committed
+            // them returns so the rows are already visible to every connection
+            pub fn subscribe(
+                &self,
+                table: &str,
+            ) -> ryde::tokio::sync::broadcast::Receiver<ryde::db::Change> {
+                self.changes.subscribe(table)
+            }
+
+            pub fn replicate(
+                &self,
+                dir: impl AsRef<std::path::Path>,
+                interval: std::time::Duration,
+                snapshot_interval: std::time::Duration,
+            ) -> ryde::tokio::task::JoinHandle<ryde::Result<()>> {
+                ryde::db::replicate(self.writer.clone(), dir, interval, snapshot_interval)
+            }
 
-            Ok(Db(connection))
+            // selects take turns on the readers, everything else goes through the writer
+            fn reader(&self) -> &tokio_rusqlite::Connection {
+                if self.readers.is_empty() {
+                    return &self.writer;
+                }
+                let next = self
+                    .next_reader
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+                &self.readers[next % self.readers.len()]
+            }
+
+            pub async fn transaction<T, F>(&self, f: F) -> ryde::Result<T>
+            where
+                T: Send + 'static,
+                F: FnOnce(&Tx) -> ryde::Result<T> + Send + 'static,
+            {
+                let result = self
+                    .writer
+                    .call(move |conn| {
+                        let tx = conn.transaction()?;
+                        let result = f(&Tx(&tx));
+                        if result.is_ok() {
+                            tx.commit()?;
+                        }
+
+                        Ok(result)
+                    })
+                    .await;
+                self.changes.flush();
+
+                result?
+            }
+
+            #(#db_impls)*
+        }
+
+        pub struct Tx<'a>(&'a rusqlite::Connection);
+
+        impl Tx<'_> {
+            pub fn transaction<T>(&self, f: impl FnOnce(&Tx) -> ryde::Result<T>) -> ryde::Result<T> {
+                self.0.execute_batch("savepoint ryde")?;
+                let changes = ryde::db::pending_changes();
+                match f(self) {
+                    Ok(value) => {
+                        self.0.execute_batch("release ryde")?;
+                        Ok(value)
+                    }
+                    Err(err) => {
+                        self.0.execute_batch("rollback to ryde; release ryde")?;
+                        ryde::db::discard_changes(changes);
+                        Err(err)
+                    }
+                }
+            }
+
+            #(#tx_impls)*
         }
 
         pub trait Queries {
@@ -73,17 +625,51 @@ fn to_tokens(output: Output) -> TokenStream {
 }
 
 fn impl_tokens(output: &Stmt) -> TokenStream {
+    let (ident, in_cols, return_type) = match fn_parts(output) {
+        Some(parts) => parts,
+        None => return quote! {},
+    };
+    let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
+    let owned_args: Vec<TokenStream> = in_cols.iter().filter_map(owned_arg_tokens).collect();
+    let param_fields: Vec<TokenStream> = in_cols.iter().map(forward_param_tokens).collect();
+    let call = match output {
+        Stmt::AggQuery { .. } | Stmt::Query { read: true, .. } => quote! {
+            self.reader()
+                .call(move |conn| Ok(Tx(conn).#ident(#(#param_fields,)*)))
+                .await?
+        },
+        _ => quote! {
+            let result = self
+                .writer
+                .call(move |conn| Ok(Tx(conn).#ident(#(#param_fields,)*)))
+                .await;
+            self.changes.flush();
+
+            result?
+        },
+    };
+
+    quote! {
+        async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<#return_type> {
+            #(#owned_args)*
+            #call
+        }
+    }
+}
+
+fn tx_impl_tokens(output: &Stmt) -> TokenStream {
     match output {
-        Stmt::ExecuteBatch { ident, sql } => quote! {
-            async fn #ident(&self) -> ryde::Result<()> {
-                self
-                    .0
-                    .call(move |conn| conn.execute_batch(#sql).map_err(|err| err.into()))
-                    .await?;
+        Stmt::ExecuteBatch { ident, sql }
+        | Stmt::CreateTable {
+            fn_ident: ident,
+            sql,
+            ..
+        } => quote! {
+            pub fn #ident(&self) -> ryde::Result<()> {
+                self.0.execute_batch(#sql)?;
 
                 Ok(())
             }
-
         },
         Stmt::Execute {
             ident,
@@ -91,17 +677,23 @@ fn impl_tokens(output: &Stmt) -> TokenStream {
             in_cols,
         } => {
             let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
+            let arg_params: Vec<TokenStream> =
+                in_cols.iter().filter_map(arg_param_tokens).collect();
             let param_fields: Vec<TokenStream> = in_cols.iter().map(param_tokens).collect();
 
+            let body = discard_on_error_tokens(
+                quote! {
+                    #(#arg_params)*
+                    let params = tokio_rusqlite::params![#(#param_fields,)*];
+
+                    Ok(self.0.prepare_cached(#sql)?.execute(params)?)
+                },
+                quote! { usize },
+            );
+
             quote! {
-                async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<usize> {
-                    Ok(self
-                        .0
-                        .call(move |conn| {
-                            let params = tokio_rusqlite::params![#(#param_fields,)*];
-                            conn.execute(#sql, params).map_err(|err| err.into())
-                        })
-                        .await?)
+                pub fn #ident(&self, #(#fn_args,)*) -> ryde::Result<usize> {
+                    #body
                 }
             }
         }
@@ -111,25 +703,27 @@ fn impl_tokens(output: &Stmt) -> TokenStream {
             in_cols,
         } => {
             let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
+            let arg_params: Vec<TokenStream> =
+                in_cols.iter().filter_map(arg_param_tokens).collect();
             let param_fields: Vec<TokenStream> = in_cols.iter().map(param_tokens).collect();
 
-            quote! {
-                 async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<i64> {
-                    let result = self.0
-                        .call(move |conn| {
-                            let mut stmt = conn.prepare(#sql)?;
-                            let params = tokio_rusqlite::params![#(#param_fields,)*];
-                            let rows = stmt.query_map(params, |row| row.get(0))?
-                                .collect::<rusqlite::Result<Vec<_>>>();
-
-                            match rows {
-                                Ok(rows) => Ok(rows.last().cloned().expect("count(*) expected")),
-                                Err(err) => Err(err.into()),
-                            }
-                        })
-                        .await?;
+            let body = discard_on_error_tokens(
+                quote! {
+                    let mut stmt = self.0.prepare_cached(#sql)?;
+                    #(#arg_params)*
+                    let params = tokio_rusqlite::params![#(#param_fields,)*];
+                    let rows = stmt
+                        .query_map(params, |row| row.get(0))?
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+                    rows.last().cloned().ok_or(ryde::Error::NotFound)
+                },
+                quote! { i64 },
+            );
 
-                    Ok(result)
+            quote! {
+                pub fn #ident(&self, #(#fn_args,)*) -> ryde::Result<i64> {
+                    #body
                 }
             }
         }
@@ -143,83 +737,172 @@ fn impl_tokens(output: &Stmt) -> TokenStream {
         } => {
             let struct_ident = match &cast {
                 Cast::T(ident) | Cast::Vec(ident) => ident.clone(),
-                Cast::None => struct_ident(&ident),
+                Cast::None => struct_ident(ident),
             };
             let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
+            let arg_params: Vec<TokenStream> =
+                in_cols.iter().filter_map(arg_param_tokens).collect();
             let param_fields: Vec<TokenStream> = in_cols.iter().map(param_tokens).collect();
             let (return_statement, return_type) = match ret {
                 QueryReturn::Row => (
-                    quote! {
-                        match rows {
-                            Ok(rows) => Ok(rows.last().unwrap().clone()),
-                            Err(err) => Err(err.into()),
-                        }
-                    },
+                    quote! { rows.last().cloned().ok_or(ryde::Error::NotFound) },
                     quote! { #struct_ident },
                 ),
                 QueryReturn::OptionRow => (
-                    quote! {
-                        match rows {
-                            Ok(rows) => Ok(rows.last().cloned()),
-                            Err(err) => Err(err.into()),
-                        }
-                    },
+                    quote! { Ok(rows.last().cloned()) },
                     quote! { Option<#struct_ident> },
                 ),
-                QueryReturn::Rows => (
+                QueryReturn::Rows => (quote! { Ok(rows) }, quote! { Vec<#struct_ident> }),
+            };
+
+            let body = discard_on_error_tokens(
+                quote! {
+                    let mut stmt = self.0.prepare_cached(#sql)?;
+                    #(#arg_params)*
+                    let params = tokio_rusqlite::params![#(#param_fields,)*];
+                    let rows = stmt
+                        .query_map(params, |row| #struct_ident::new(row))?
+                        .collect::<rusqlite::Result<Vec<#struct_ident>>>()?;
+
+                    #return_statement
+                },
+                return_type.clone(),
+            );
+
+            quote! {
+                pub fn #ident(&self, #(#fn_args,)*) -> ryde::Result<#return_type> {
+                    #body
+                }
+            }
+        }
+        Stmt::InsertMany {
+            ident,
+            sql,
+            in_cols,
+            rows,
+            returning,
+        } => {
+            let fn_args: Vec<TokenStream> = rows.iter().map(fn_tokens).collect();
+            let names: Vec<TokenStream> = in_cols.iter().map(param_tokens).collect();
+            let row_pattern = match &names[..] {
+                [name] => quote!(#name),
+                _ => quote!((#(#names),*)),
+            };
+            let arg_params: Vec<TokenStream> =
+                in_cols.iter().filter_map(arg_param_tokens).collect();
+            let (init, run, result, return_type) = match returning {
+                Some(struct_ident) => (
+                    quote! { let mut results = vec![]; },
                     quote! {
-                        rows.map_err(|err| err.into())
+                        results.extend(
+                            stmt.query_map(params, |row| #struct_ident::new(row))?
+                                .collect::<rusqlite::Result<Vec<#struct_ident>>>()?,
+                        );
                     },
+                    quote! { results },
                     quote! { Vec<#struct_ident> },
                 ),
+                None => (
+                    quote! { let mut changes = 0; },
+                    quote! { changes += stmt.execute(params)?; },
+                    quote! { changes },
+                    quote! { usize },
+                ),
             };
+
             quote! {
-                async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<#return_type> {
-                    Ok(self.0
-                        .call(move |conn| {
-                            let mut stmt = conn.prepare(#sql)?;
-                            let params = tokio_rusqlite::params![#(#param_fields,)*];
-                            let rows = stmt
-                                .query_map(params, |row| #struct_ident::new(row))?
-                                .collect::<rusqlite::Result<Vec<#struct_ident>>>();
-                            #return_statement
-                        })
-                        .await?)
+                pub fn #ident(&self, #(#fn_args,)*) -> ryde::Result<#return_type> {
+                    self.transaction(|tx| {
+                        let mut stmt = tx.0.prepare_cached(#sql)?;
+                        #init
+                        for #row_pattern in rows {
+                            #(#arg_params)*
+                            let params = tokio_rusqlite::params![#(#names,)*];
+                            #run
+                        }
+
+                        Ok(#result)
+                    })
                 }
             }
         }
-        Stmt::CreateTable { sql, fn_ident, .. } => {
-            quote! {
-                async fn #fn_ident(&self) -> ryde::Result<()> {
-                    self.0
-                        .call(move |conn| conn.execute_batch(#sql).map_err(|err| err.into()))
-                        .await?;
+        Stmt::Migrate { .. } => quote! {},
+    }
+}
 
-                    Ok(())
-                }
-            }
+// sqlite undoes a failed statement on its own without calling the rollback hook,
+// its changes are dropped here even when the transaction around it commits
+fn discard_on_error_tokens(body: TokenStream, return_type: TokenStream) -> TokenStream {
+    quote! {
+        let changes = ryde::db::pending_changes();
+        let result = (|| -> ryde::Result<#return_type> { #body })();
+        if result.is_err() {
+            ryde::db::discard_changes(changes);
         }
+
+        result
     }
 }
 
-fn trait_tokens(output: &Stmt) -> TokenStream {
+fn db_impl_tokens(output: &Stmt) -> TokenStream {
     match output {
-        Stmt::ExecuteBatch { ident, .. } => quote! {
-            async fn #ident(&self) -> ryde::Result<()>;
-        },
-        Stmt::Execute { ident, in_cols, .. } => {
-            let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
+        Stmt::Migrate { ident, sqls, .. } => quote! {
+            pub async fn #ident(&self) -> ryde::Result<()> {
+                let migrations: &[&str] = &[#(#sqls,)*];
+                let version = self
+                    .writer
+                    .call(move |conn| {
+                        let tx = conn.transaction_with_behavior(
+                            rusqlite::TransactionBehavior::Immediate,
+                        )?;
+                        let version: usize =
+                            tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+                        if version < migrations.len() {
+                            for sql in &migrations[version..] {
+                                tx.execute_batch(sql)?;
+                            }
+                            tx.pragma_update(None, "user_version", migrations.len())?;
+                        }
+                        tx.commit()?;
 
-            quote! {
-                async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<usize>;
-            }
-        }
-        Stmt::AggQuery { ident, in_cols, .. } => {
-            let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
-            quote! {
-                async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<i64>;
+                        Ok(version)
+                    })
+                    .await;
+                self.changes.flush();
+                let version = version?;
+
+                if version > migrations.len() {
+                    return Err(ryde::Error::Migration(format!(
+                        "database is at version {} but only {} migrations are defined",
+                        version,
+                        migrations.len()
+                    )));
+                }
+
+                Ok(())
             }
-        }
+        },
+        _ => quote! {},
+    }
+}
+
+fn trait_tokens(output: &Stmt) -> TokenStream {
+    let (ident, in_cols, return_type) = match fn_parts(output) {
+        Some(parts) => parts,
+        None => return quote! {},
+    };
+    let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
+
+    quote! {
+        async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<#return_type>;
+    }
+}
+
+fn fn_parts(output: &Stmt) -> Option<(&Ident, &[Column], TokenStream)> {
+    match output {
+        Stmt::ExecuteBatch { ident, .. } => Some((ident, &[], quote! { () })),
+        Stmt::Execute { ident, in_cols, .. } => Some((ident, in_cols, quote! { usize })),
+        Stmt::AggQuery { ident, in_cols, .. } => Some((ident, in_cols, quote! { i64 })),
         Stmt::Query {
             cast,
             ident,
@@ -229,30 +912,40 @@ fn trait_tokens(output: &Stmt) -> TokenStream {
         } => {
             let struct_ident = match &cast {
                 Cast::T(ident) | Cast::Vec(ident) => ident.clone(),
-                Cast::None => struct_ident(&ident),
+                Cast::None => struct_ident(ident),
             };
-            let fn_args: Vec<TokenStream> = in_cols.iter().map(fn_tokens).collect();
             let return_type = match ret {
                 QueryReturn::Row => quote! { #struct_ident },
                 QueryReturn::OptionRow => quote! { Option<#struct_ident> },
                 QueryReturn::Rows => quote! { Vec<#struct_ident> },
             };
 
-            quote! {
-                async fn #ident(&self, #(#fn_args,)*) -> ryde::Result<#return_type>;
-            }
+            Some((ident, in_cols, return_type))
         }
-        Stmt::CreateTable { fn_ident, .. } => {
-            quote! {
-                async fn #fn_ident(&self) -> ryde::Result<()>;
-            }
+        Stmt::CreateTable { fn_ident, .. } => Some((fn_ident, &[], quote! { () })),
+        Stmt::InsertMany {
+            ident,
+            rows,
+            returning,
+            ..
+        } => {
+            let return_type = match returning {
+                Some(struct_ident) => quote! { Vec<#struct_ident> },
+                None => quote! { usize },
+            };
+
+            Some((ident, rows, return_type))
         }
+        Stmt::Migrate { .. } => None,
     }
 }
 
 fn stmt_tokens(output: Stmt) -> TokenStream {
     match output {
-        Stmt::ExecuteBatch { .. } | Stmt::Execute { .. } | Stmt::AggQuery { .. } => quote! {},
+        Stmt::ExecuteBatch { .. }
+        | Stmt::Execute { .. }
+        | Stmt::AggQuery { .. }
+        | Stmt::InsertMany { .. } => quote! {},
         Stmt::Query {
             ident,
             in_cols,
@@ -275,17 +968,25 @@ fn stmt_tokens(output: Stmt) -> TokenStream {
             let struct_fields: Vec<TokenStream> = out_cols.iter().map(column_tokens).collect();
             let instance_fields: Vec<TokenStream> = out_cols.iter().map(row_tokens).collect();
 
+            let default_fields: Vec<TokenStream> = out_cols.iter().map(default_tokens).collect();
+
             let struct_tokens = match &cast {
                 Cast::None => quote! {
-                    #[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
+                    #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
                     #[serde(crate = "crate::serde")]
                     pub struct #struct_ident {
                         #(#struct_fields,)*
                     }
 
+                    impl Default for #struct_ident {
+                        fn default() -> Self {
+                            Self { #(#default_fields,)* }
+                        }
+                    }
+
                     impl #struct_ident {
                         pub fn new(row: &tokio_rusqlite::Row<'_>) -> rusqlite::Result<Self> {
-                            Ok(Self { #(#instance_fields,)* ..Default::default() })
+                            Ok(Self { #(#instance_fields,)* })
                         }
 
                         pub fn names() -> #name_struct_ident {
@@ -312,40 +1013,65 @@ fn stmt_tokens(output: Stmt) -> TokenStream {
                 Cast::T(ident) | Cast::Vec(ident) => ident,
                 Cast::None => struct_ident(&fn_ident),
             };
-            let struct_fields: Vec<TokenStream> = cols.iter().map(column_tokens).collect();
-            let instance_fields: Vec<TokenStream> = cols.iter().map(row_tokens).collect();
-            let name_struct_ident = Ident::new(
-                &format!("{}Names", &struct_ident.to_string()),
-                Span::call_site(),
-            );
-            let name_struct_fields: Vec<TokenStream> =
-                cols.iter().map(name_struct_tokens).collect();
-            let name_struct_self_fields: Vec<TokenStream> =
-                cols.iter().map(name_struct_self_tokens).collect();
 
-            let tokens = quote! {
-                #[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
-                #[serde(crate = "crate::serde")]
-                pub struct #struct_ident {
-                    #(#struct_fields,)*
-                }
+            table_struct_tokens(&struct_ident, &cols)
+        }
+        Stmt::Migrate { tables, .. } => {
+            let tokens: Vec<TokenStream> = tables
+                .iter()
+                .map(|(struct_ident, cols)| table_struct_tokens(struct_ident, cols))
+                .collect();
 
-                impl #struct_ident {
-                    pub fn new(row: &tokio_rusqlite::Row<'_>) -> rusqlite::Result<Self> {
-                        let mut output = Self::default();
-                        Ok(Self { #(#instance_fields,)* ..Default::default() })
-                    }
+            quote! { #(#tokens)* }
+        }
+    }
+}
 
-                    pub fn names() -> #name_struct_ident {
-                        #name_struct_ident { #(#name_struct_self_fields,)* }
-                    }
-                }
+fn table_struct_tokens(struct_ident: &Ident, cols: &[Column]) -> TokenStream {
+    let struct_fields: Vec<TokenStream> = cols.iter().map(column_tokens).collect();
+    let instance_fields: Vec<TokenStream> = cols.iter().map(row_tokens).collect();
+    let name_struct_ident = Ident::new(
+        &format!("{}Names", &struct_ident.to_string()),
+        Span::call_site(),
+    );
+    let name_struct_fields: Vec<TokenStream> = cols.iter().map(name_struct_tokens).collect();
+    let name_struct_self_fields: Vec<TokenStream> =
+        cols.iter().map(name_struct_self_tokens).collect();
+    let default_fields: Vec<TokenStream> = cols.iter().map(default_tokens).collect();
+    let enums: Vec<TokenStream> = cols
+        .iter()
+        .filter_map(|c| match c.data_type.inner() {
+            DataType::Enum(ident, values) => Some(enum_tokens(ident, values)),
+            _ => None,
+        })
+        .collect();
 
-                pub struct #name_struct_ident { #(#name_struct_fields,)* }
-            };
+    quote! {
+        #(#enums)*
+
+        #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+        #[serde(crate = "crate::serde")]
+        pub struct #struct_ident {
+            #(#struct_fields,)*
+        }
+
+        impl Default for #struct_ident {
+            fn default() -> Self {
+                Self { #(#default_fields,)* }
+            }
+        }
 
-            tokens
+        impl #struct_ident {
+            pub fn new(row: &tokio_rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+                Ok(Self { #(#instance_fields,)* })
+            }
+
+            pub fn names() -> #name_struct_ident {
+                #name_struct_ident { #(#name_struct_self_fields,)* }
+            }
         }
+
+        pub struct #name_struct_ident { #(#name_struct_fields,)* }
     }
 }
 
@@ -356,37 +1082,71 @@ enum QueryReturn {
     Rows,
 }
 
-fn to_stmt(db_columns: &HashSet<Column>, sql_expr: SqlExpr) -> Option<Stmt> {
+fn to_stmt(db_columns: &[Column], sql_expr: SqlExpr) -> Result<Option<Stmt>> {
     let SqlExpr {
         ident,
         sql,
+        span,
         statements,
         cast,
+        relation,
+        ..
     } = sql_expr;
+    let error_ident = ident.clone();
+    let sql = list_params(&sql).unwrap_or(sql);
     // last one is the only one that returns anything?
-    match statements.last() {
+    let stmt = match statements.last() {
         Some(stmt) => match stmt {
-            Statement::CreateTable { name, columns, .. } => {
-                create_table_stmt(db_columns, name.to_string(), cast, ident, sql, columns)
-            }
-            Statement::Insert {
-                table_name,
+            Statement::CreateTable { name, .. } => Ok(create_table_stmt(
+                db_columns,
+                name.to_string(),
+                cast,
+                ident,
+                sql,
+            )),
+            Statement::CreateView {
+                name,
                 columns,
-                returning,
-                source,
-                on,
+                query,
                 ..
-            } => insert_stmt(
+            } => view_stmt(
+                db_columns,
+                name.to_string(),
+                columns,
+                query,
+                cast,
+                ident,
+                sql,
+            ),
+            Statement::CreateVirtualTable {
+                name, module_name, ..
+            } if is_fts5(module_name) => Ok(create_table_stmt(
                 db_columns,
+                name.to_string(),
+                cast,
                 ident,
                 sql,
-                table_name.to_string(),
+            )),
+            Statement::Insert {
+                table_name,
                 columns,
                 returning,
                 source,
-                cast,
                 on,
-            ),
+                ..
+            } => coalesce_defaults(db_columns, &statements, sql).and_then(|sql| {
+                insert_stmt(
+                    db_columns,
+                    ident,
+                    sql,
+                    table_name.to_string(),
+                    columns,
+                    returning,
+                    source,
+                    cast,
+                    on,
+                )
+            }),
             Statement::Update {
                 table,
                 assignments,
@@ -410,27 +1170,262 @@ fn to_stmt(db_columns: &HashSet<Column>, sql_expr: SqlExpr) -> Option<Stmt> {
                 returning,
                 ..
             } => delete_stmt(db_columns, ident, sql, from, selection, returning, cast),
-            Statement::Query(d) => {
-                let Query { body, limit, .. } = &**d;
-                query_stmt(db_columns, ident, sql, body, limit.as_ref(), cast)
-            }
-            _ => Some(Stmt::ExecuteBatch { ident, sql }),
+            Statement::Query(query) => query_stmt(db_columns, ident, sql, query, cast),
+            _ => Ok(Some(Stmt::ExecuteBatch { ident, sql })),
         },
-        _ => None,
+        _ => Ok(None),
+    };
+
+    stmt.and_then(|mut stmt| match stmt {
+        Some(
+            Stmt::Execute {
+                ref mut in_cols, ..
+            }
+            | Stmt::AggQuery {
+                ref mut in_cols, ..
+            }
+            | Stmt::Query {
+                ref mut in_cols, ..
+            },
+        ) => {
+            *in_cols = params(std::mem::take(in_cols))?;
+            if let Some(column) = relation {
+                *in_cols = vec![column];
+            }
+            Ok(stmt)
+        }
+        stmt => Ok(stmt),
+    })
+    .map_err(|err| err.into_syn_error(&error_ident, span))
+}
+
+// `in (?)` becomes `in (select value from json_each(?))` so a vec can be bound as a json array,
+// the rest of the sql is left as written
+fn list_params(sql: &str) -> Option<String> {
+    let tokens = Tokenizer::new(&SQLiteDialect {}, sql)
+        .tokenize_with_location()
+        .ok()?;
+    let tokens = tokens
+        .iter()
+        .filter(|t| !matches!(t.token, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+    let mut placeholders = vec![];
+    for (i, t) in tokens.iter().enumerate() {
+        if !matches!(&t.token, Token::Word(word) if word.keyword == Keyword::IN) {
+            continue;
+        }
+        // :name and @name are two tokens
+        let placeholder = match &tokens[i + 1..] {
+            [lparen, placeholder, rparen, ..]
+                if lparen.token == Token::LParen
+                    && matches!(placeholder.token, Token::Placeholder(_))
+                    && rparen.token == Token::RParen =>
+            {
+                vec![*placeholder]
+            }
+            [lparen, prefix, name, rparen, ..]
+                if lparen.token == Token::LParen
+                    && matches!(prefix.token, Token::Colon | Token::AtSign)
+                    && matches!(name.token, Token::Word(_))
+                    && rparen.token == Token::RParen =>
+            {
+                vec![*prefix, *name]
+            }
+            _ => continue,
+        };
+        let token = placeholder
+            .iter()
+            .map(|t| t.token.to_string())
+            .collect::<String>();
+        placeholders.push((placeholder[0].location, token));
+    }
+    if placeholders.is_empty() {
+        return None;
+    }
+    let mut sql = sql.to_string();
+    // later placeholders first so earlier offsets stay put
+    for (location, token) in placeholders.into_iter().rev() {
+        let offset = location_offset(&sql, location)?;
+        sql.replace_range(
+            offset..offset + token.len(),
+            &format!("select value from json_each({})", token),
+        );
+    }
+
+    Some(sql)
+}
+
+// byte offset of a tokenizer location, lines and columns start at 1
+fn location_offset(sql: &str, location: Location) -> Option<usize> {
+    let mut lines = sql.split_inclusive('\n');
+    let before = lines
+        .by_ref()
+        .take(location.line as usize - 1)
+        .map(str::len)
+        .sum::<usize>();
+    let column = lines
+        .next()?
+        .chars()
+        .take(location.column as usize - 1)
+        .map(char::len_utf8)
+        .sum::<usize>();
+
+    Some(before + column)
+}
+
+// `values (?)` becomes `values (coalesce(?, default))` for not null columns with a default
+// so inserts can pass None to get the schema default, only the placeholders are rewritten
+fn coalesce_defaults(
+    db_columns: &[Column],
+    statements: &[Statement],
+    sql: String,
+) -> SqlResult<String> {
+    let Some(Statement::Insert {
+        table_name,
+        columns,
+        source: Some(source),
+        ..
+    }) = statements.last()
+    else {
+        return Ok(sql);
+    };
+    let SetExpr::Values(values) = &*source.body else {
+        return Ok(sql);
+    };
+    let defaults = values
+        .rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .zip(row)
+                .map(|(column, expr)| match expr {
+                    sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(_)) => {
+                        db_columns
+                            .iter()
+                            .find(|c| {
+                                c.table_name == table_name.to_string() && c.name == column.value
+                            })
+                            .filter(|c| c.has_default())
+                            .and_then(|c| c.default.clone())
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let expected = defaults.iter().flatten().flatten().count();
+    if expected == 0 {
+        return Ok(sql);
+    }
+    let error = || {
+        SqlError::new("the column defaults could not be added to the values")
+            .hint("pass the default yourself, like `coalesce(?, 0)`")
+    };
+    let tokens = Tokenizer::new(&SQLiteDialect {}, &sql)
+        .tokenize_with_location()
+        .map_err(|_| error())?;
+    let tokens = tokens
+        .iter()
+        .filter(|t| !matches!(t.token, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+    // the insert is the last statement and its values keyword is outside any parens
+    let mut depth = 0;
+    let mut values = None;
+    for (i, t) in tokens.iter().enumerate() {
+        match &t.token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::SemiColon if depth == 0 && i + 1 < tokens.len() => values = None,
+            Token::Word(word) if word.keyword == Keyword::VALUES && depth == 0 => {
+                values = values.or(Some(i))
+            }
+            _ => {}
+        }
     }
+    let values = values.ok_or_else(error)?;
+    let (mut row, mut expr, mut from) = (0, 0, 0);
+    let mut placeholders = vec![];
+    depth = 0;
+    for (i, t) in tokens.iter().enumerate().skip(values + 1) {
+        match &t.token {
+            Token::LParen => {
+                depth += 1;
+                if depth == 1 {
+                    from = i + 1;
+                }
+            }
+            Token::Comma | Token::RParen if depth == 1 => {
+                if let Some(default) = defaults
+                    .get(row)
+                    .and_then(|row| row.get(expr))
+                    .cloned()
+                    .flatten()
+                {
+                    // :name and @name are two tokens
+                    let token = match &tokens[from..i] {
+                        [placeholder] if matches!(placeholder.token, Token::Placeholder(_)) => {
+                            vec![*placeholder]
+                        }
+                        [prefix, name]
+                            if matches!(prefix.token, Token::Colon | Token::AtSign)
+                                && matches!(name.token, Token::Word(_)) =>
+                        {
+                            vec![*prefix, *name]
+                        }
+                        _ => return Err(error()),
+                    };
+                    let text = token
+                        .iter()
+                        .map(|t| t.token.to_string())
+                        .collect::<String>();
+                    placeholders.push((token[0].location, text, default));
+                }
+                expr += 1;
+                from = i + 1;
+                if t.token == Token::RParen {
+                    depth = 0;
+                    row += 1;
+                    expr = 0;
+                }
+            }
+            Token::RParen => depth -= 1,
+            Token::Comma if depth == 0 => {}
+            _ if depth == 0 => break,
+            _ => {}
+        }
+    }
+    if placeholders.len() != expected {
+        return Err(error());
+    }
+    let mut rewritten = sql.clone();
+    // later placeholders first so earlier offsets stay put
+    for (location, text, default) in placeholders.into_iter().rev() {
+        let offset = location_offset(&sql, location).ok_or_else(error)?;
+        if sql.get(offset..offset + text.len()) != Some(text.as_str()) {
+            return Err(error());
+        }
+        rewritten.replace_range(
+            offset..offset + text.len(),
+            &format!("coalesce({}, {})", text, default),
+        );
+    }
+    Parser::parse_sql(&SQLiteDialect {}, &rewritten).map_err(|_| error())?;
+
+    Ok(rewritten)
 }
 
 fn create_table_stmt(
-    _db_columns: &HashSet<Column>,
+    db_columns: &[Column],
     table_name: String,
     cast: Cast,
     fn_ident: Ident,
     sql: String,
-    columns: &Vec<sqlparser::ast::ColumnDef>,
 ) -> Option<Stmt> {
-    let cols = columns
+    let cols = db_columns
         .iter()
-        .map(|c| column(Some(&table_name), c))
+        .filter(|c| c.table_name == table_name && !c.hidden)
+        .cloned()
         .collect::<Vec<_>>();
 
     Some(Stmt::CreateTable {
@@ -441,107 +1436,519 @@ fn create_table_stmt(
     })
 }
 
+// views get a row struct like tables
+fn view_stmt(
+    db_columns: &[Column],
+    view_name: String,
+    columns: &[ViewColumnDef],
+    query: &Query,
+    cast: Cast,
+    fn_ident: Ident,
+    sql: String,
+) -> SqlResult<Option<Stmt>> {
+    let cols = db_columns
+        .iter()
+        .filter(|c| c.table_name == view_name)
+        .collect::<Vec<_>>();
+    // schema skips views it can't type, this surfaces why instead of an empty struct
+    if cols.is_empty() {
+        let names = columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        let (_, view_cols) = query_columns(db_columns, query)?;
+        alias_columns(&view_name, &names, view_cols)?;
+        return Err(SqlError::new(format!(
+            "the columns of view {} could not be inferred",
+            view_name
+        ))
+        .hint("select named columns from tables created before the view"));
+    }
+    let duplicate = cols
+        .iter()
+        .enumerate()
+        .find(|(i, c)| cols[..*i].iter().any(|other| other.name == c.name));
+    if let Some((_, column)) = duplicate {
+        return Err(
+            SqlError::new(format!("column {} is selected more than once", column.name))
+                .hint("alias one of them, like `likes.id as like_id`"),
+        );
+    }
+    if let Some(column) = cols
+        .iter()
+        .find(|c| syn::parse_str::<Ident>(&c.name).is_err())
+    {
+        return Err(
+            SqlError::new("expressions in the select list need an alias")
+                .fragment(&column.name)
+                .hint("name it with `as`, like `sum(amount) as total`"),
+        );
+    }
+
+    Ok(create_table_stmt(
+        db_columns, view_name, cast, fn_ident, sql,
+    ))
+}
+
+fn migrate_stmt(migrations: Vec<SqlExpr>) -> Result<Option<Stmt>> {
+    let ident = match migrations.first() {
+        Some(def) => def.ident.clone(),
+        None => return Ok(None),
+    };
+    if let Some(def) = migrations.iter().find(|def| def.ident != ident) {
+        return Err(syn::Error::new(
+            def.ident.span(),
+            format!(
+                "{}: only one migrations block is supported, {} is already defined",
+                def.ident, ident
+            ),
+        ));
+    }
+    let schema = schema(&migrations);
+    // enums are named after the struct, without one every table would get Migrate* enums
+    combine_errors(migrations.iter().map(|def| {
+        let table_name = def.statements.iter().find_map(|stmt| match stmt {
+            Statement::CreateTable { name, .. } => Some(name.to_string()),
+            _ => None,
+        });
+        let enum_column = schema.iter().find(|c| {
+            Some(&c.table_name) == table_name.as_ref()
+                && matches!(c.data_type.inner(), DataType::Enum(..))
+        });
+        match (&def.cast, table_name, enum_column) {
+            (Cast::None, Some(table_name), Some(column)) => Err(syn::Error::new(
+                def.span,
+                format!(
+                    "{}: {}.{} is an enum so the table needs a struct name like `as {}`",
+                    def.ident,
+                    table_name,
+                    column.name,
+                    snake_to_pascal(table_name.clone())
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }))?;
+    let tables = migrations
+        .iter()
+        .filter_map(|def| {
+            let struct_ident = match &def.cast {
+                Cast::T(ident) | Cast::Vec(ident) => ident.clone(),
+                Cast::None => return None,
+            };
+            let table_name = def.statements.iter().find_map(|stmt| match stmt {
+                Statement::CreateTable { name, .. } => Some(name.to_string()),
+                _ => None,
+            })?;
+            let cols = schema
+                .iter()
+                .filter(|c| c.table_name == table_name)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            Some((struct_ident, cols))
+        })
+        .collect();
+    let sqls = migrations.into_iter().map(|def| def.sql).collect();
+
+    Ok(Some(Stmt::Migrate {
+        ident,
+        sqls,
+        tables,
+    }))
+}
+
 fn query_stmt(
-    db_cols: &HashSet<Column>,
+    db_cols: &[Column],
     ident: Ident,
     sql: String,
-    body: &SetExpr,
-    limit: Option<&sqlparser::ast::Expr>,
+    query: &Query,
     cast: Cast,
-) -> Option<Stmt> {
-    let select = match body {
-        SetExpr::Select(select) => select,
-        SetExpr::Insert(Statement::Insert {
-            table_name,
+) -> SqlResult<Option<Stmt>> {
+    if let SetExpr::Insert(Statement::Insert {
+        table_name,
+        columns,
+        returning,
+        source,
+        on,
+        ..
+    }) = &*query.body
+    {
+        return insert_stmt(
+            db_cols,
+            ident,
+            sql,
+            table_name.to_string(),
             columns,
             returning,
             source,
+            cast,
             on,
-            ..
-        }) => {
-            return insert_stmt(
-                db_cols,
-                ident,
-                sql,
-                table_name.to_string(),
-                columns,
-                returning,
-                source,
-                cast,
-                on,
-            );
+        );
+    }
+    let (in_cols, out_cols) = query_columns(db_cols, query)?;
+    if cast == Cast::None {
+        let duplicate = out_cols
+            .iter()
+            .enumerate()
+            .find(|(i, c)| out_cols[..*i].iter().any(|other| other.name == c.name));
+        if let Some((_, column)) = duplicate {
+            return Err(SqlError::new(format!(
+                "column {} is selected more than once",
+                column.name
+            ))
+            .hint("alias one of them, like `likes.id as like_id`"));
         }
-        _ => {
-            return None;
+        let unnamed = out_cols
+            .iter()
+            .find(|c| syn::parse_str::<Ident>(&c.name).is_err());
+        match unnamed {
+            Some(Column {
+                label: Some(label), ..
+            }) => {
+                return Err(
+                    SqlError::new(format!("alias {} is not a valid rust name", label))
+                        .hint("alias columns with letters, numbers and underscores"),
+                )
+            }
+            Some(column) => {
+                return Err(
+                    SqlError::new("expressions in the select list need an alias")
+                        .fragment(&column.name)
+                        .hint("name it with `as`, like `sum(amount) as total`"),
+                )
+            }
+            None => {}
         }
+    }
+    let ret = match &query.limit {
+        Some(sqlparser::ast::Expr::Value(sqlparser::ast::Value::Number(number, _))) => {
+            match number.as_str() {
+                "1" => QueryReturn::OptionRow,
+                _ => QueryReturn::Rows,
+            }
+        }
+        _ => QueryReturn::Rows,
+    };
+    // one column and it's count(*)
+    let ret = match out_cols[..] {
+        [Column {
+            ref column_type, ..
+        }] => match column_type {
+            ColumnType::Aggregate => {
+                return Ok(Some(Stmt::AggQuery {
+                    ident,
+                    sql,
+                    in_cols,
+                }))
+            }
+            ColumnType::Column => ret,
+        },
+        _ => ret,
     };
+
+    Ok(Some(Stmt::Query {
+        ident,
+        sql,
+        in_cols,
+        out_cols,
+        ret,
+        cast,
+        read: true,
+    }))
+}
+
+// input params and output columns of a query, ctes become tables for the rest of it
+fn query_columns(db_cols: &[Column], query: &Query) -> SqlResult<(Vec<Column>, Vec<Column>)> {
+    let mut env = db_cols.to_vec();
+    let mut in_cols = vec![];
+    for cte in query.with.iter().flat_map(|with| &with.cte_tables) {
+        let name = &cte.alias.name.value;
+        // a recursive cte gets its columns from the first select in the union
+        let mut anchor = &*cte.query.body;
+        while let SetExpr::SetOperation { left, .. } = anchor {
+            anchor = left;
+        }
+        let (_, anchor_cols) = set_expr_columns(&env, anchor)?;
+        let cte_cols = alias_columns(name, &cte.alias.columns, anchor_cols)?;
+        let cte_env = [cte_cols.clone(), env.clone()].concat();
+        in_cols.extend(query_columns(&cte_env, &cte.query)?.0);
+        env = [cte_cols, env].concat();
+    }
+    let (params, out_cols) = set_expr_columns(&env, &query.body)?;
+    in_cols.extend(params);
+    for order_by in &query.order_by {
+        in_cols.extend(subquery_params(&env, &order_by.expr)?);
+    }
+    // `limit ? offset ?` pages through results
+    let limits = [
+        ("limit", query.limit.as_ref()),
+        ("offset", query.offset.as_ref().map(|offset| &offset.value)),
+    ];
+    for (name, expr) in limits {
+        match expr {
+            Some(sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token))) => in_cols
+                .push(Column {
+                    name: name.into(),
+                    full_name: name.into(),
+                    data_type: DataType::Integer,
+                    placeholder: Some(token.clone()),
+                    ..Default::default()
+                }),
+            Some(expr) => in_cols.extend(subquery_params(&env, expr)?),
+            None => {}
+        }
+    }
+
+    Ok((in_cols, out_cols))
+}
+
+fn set_expr_columns(env: &[Column], body: &SetExpr) -> SqlResult<(Vec<Column>, Vec<Column>)> {
+    match body {
+        SetExpr::Select(select) => select_columns(env, select),
+        SetExpr::Query(query) => query_columns(env, query),
+        SetExpr::SetOperation { left, right, .. } => {
+            let (mut in_cols, out_cols) = set_expr_columns(env, left)?;
+            let (right_in_cols, right_out_cols) = set_expr_columns(env, right)?;
+            if out_cols.len() != right_out_cols.len() {
+                return Err(SqlError::new(format!(
+                    "{} columns on the left and {} on the right",
+                    out_cols.len(),
+                    right_out_cols.len()
+                ))
+                .fragment(body)
+                .hint("select the same number of columns on both sides"));
+            }
+            in_cols.extend(right_in_cols);
+            let out_cols = out_cols
+                .into_iter()
+                .zip(right_out_cols)
+                .map(|(left, right)| Column {
+                    data_type: left.data_type.union(&right.data_type),
+                    ..left
+                })
+                .collect();
+
+            Ok((in_cols, out_cols))
+        }
+        body => Err(SqlError::new("unsupported query")
+            .fragment(body)
+            .hint("use select, with, union, intersect or except")),
+    }
+}
+
+fn select_columns(env: &[Column], select: &Select) -> SqlResult<(Vec<Column>, Vec<Column>)> {
     let Select {
         projection,
         selection,
         from,
+        group_by,
+        having,
         ..
-    } = &**select;
-    // make sure the table in from matches something in db_cols
-    let schema_tables = db_cols
-        .iter()
-        .map(|col| &col.table_name)
-        .collect::<HashSet<_>>();
-    from.iter()
-        .map(|f| match &f.relation {
-            TableFactor::Table { name, .. } => name.to_string(),
-            _ => todo!("table"),
-        })
-        .for_each(|table| {
-            if !schema_tables.contains(&table) {
-                panic!("{}: table name does not exist {}", ident, table);
+    } = select;
+    let mut in_cols = vec![];
+    let mut scope = vec![];
+    for table in from {
+        let (params, columns) = table_columns(env, table)?;
+        in_cols.extend(params);
+        scope.extend(columns);
+    }
+    // the tables in from shadow the rest for correlated subqueries
+    let lookup = [scope.clone(), env.to_vec()].concat();
+    let mut params = vec![];
+    let mut out_cols = vec![];
+    for select_item in projection {
+        match select_item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                params.extend(subquery_params(&lookup, expr)?)
             }
-        });
-    let in_cols = match selection {
-        Some(expr) => columns_from_expr(&db_cols, expr, None),
-        None => vec![],
-    };
-    let out_cols = projection
-        .iter()
-        .flat_map(|si| columns_from_select_item(&db_cols, si, &cast))
-        .collect::<Vec<_>>();
-    let ret = match limit {
-        Some(sqlparser::ast::Expr::Value(sqlparser::ast::Value::Number(number, _))) => {
-            match number.as_str() {
-                "1" => QueryReturn::OptionRow,
-                _ => QueryReturn::Rows,
+            _ => {}
+        }
+        match select_item {
+            // only the tables in from, not ones with the same name further out
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => {
+                out_cols.extend(columns_from_select_item(&scope, select_item)?)
+            }
+            select_item => out_cols.extend(columns_from_select_item(&lookup, select_item)?),
+        }
+    }
+    // select list params come before the from clause
+    params.extend(in_cols);
+    if let Some(expr) = selection {
+        params.extend(columns_from_expr(&lookup, expr, None)?);
+    }
+    if let sqlparser::ast::GroupByExpr::Expressions(exprs) = group_by {
+        for expr in exprs {
+            params.extend(subquery_params(&lookup, expr)?);
+        }
+    }
+    // having can also compare the aliases in the select list
+    if let Some(expr) = having {
+        let lookup = [out_cols.clone(), lookup].concat();
+        params.extend(columns_from_expr(&lookup, expr, None)?);
+    }
+
+    Ok((params, out_cols))
+}
+
+fn table_columns(env: &[Column], table: &TableWithJoins) -> SqlResult<(Vec<Column>, Vec<Column>)> {
+    let (mut in_cols, mut columns) = relation_columns(env, &table.relation)?;
+    for join in &table.joins {
+        let (left_nullable, right_nullable) = join_nullability(&join.join_operator);
+        let (params, right) = relation_columns(env, &join.relation)?;
+        in_cols.extend(params);
+        columns = columns
+            .into_iter()
+            .map(|c| c.nullable(left_nullable))
+            .chain(right.into_iter().map(|c| c.nullable(right_nullable)))
+            .collect();
+        let constraint = match &join.join_operator {
+            JoinOperator::Inner(constraint)
+            | JoinOperator::LeftOuter(constraint)
+            | JoinOperator::RightOuter(constraint)
+            | JoinOperator::FullOuter(constraint) => Some(constraint),
+            _ => None,
+        };
+        if let Some(JoinConstraint::On(expr)) = constraint {
+            let lookup = [columns.clone(), env.to_vec()].concat();
+            in_cols.extend(columns_from_expr(&lookup, expr, None)?);
+        }
+    }
+
+    Ok((in_cols, columns))
+}
+
+fn relation_columns(
+    env: &[Column],
+    relation: &TableFactor,
+) -> SqlResult<(Vec<Column>, Vec<Column>)> {
+    match relation {
+        TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } => {
+            let name = name.to_string();
+            let columns = env
+                .iter()
+                .filter(|c| c.table_name == name)
+                .cloned()
+                .collect::<Vec<_>>();
+            if columns.is_empty() {
+                return Err(SqlError::new(format!("table {} does not exist", name))
+                    .hint("create the table in this db! block before querying it"));
+            }
+            match alias {
+                Some(alias) => Ok((
+                    vec![],
+                    alias_columns(&alias.name.value, &alias.columns, columns)?,
+                )),
+                None => Ok((vec![], columns)),
             }
         }
-        _ => QueryReturn::Rows,
-    };
-    // one column and it's count(*)
-    let ret = match out_cols[..] {
-        [Column {
-            ref column_type, ..
-        }] => match column_type {
-            ColumnType::Aggregate => {
-                return Some(Stmt::AggQuery {
-                    ident,
-                    sql,
+        // table valued functions like json_each don't add typed columns
+        TableFactor::Table { args: Some(_), .. } => Ok((vec![], vec![])),
+        TableFactor::Derived {
+            subquery, alias, ..
+        } => {
+            let (in_cols, columns) = query_columns(env, subquery)?;
+            match alias {
+                Some(alias) => Ok((
                     in_cols,
-                })
+                    alias_columns(&alias.name.value, &alias.columns, columns)?,
+                )),
+                None => Ok((in_cols, alias_columns("", &[], columns)?)),
             }
-            ColumnType::Column => ret,
-        },
-        _ => ret,
+        }
+        TableFactor::NestedJoin {
+            table_with_joins, ..
+        } => table_columns(env, table_with_joins),
+        relation => Err(SqlError::new("unsupported table expression")
+            .fragment(relation)
+            .hint("select from tables, ctes or subqueries")),
+    }
+}
+
+// the columns of a cte, subquery or aliased table as seen from the outside
+fn alias_columns(
+    table_name: &str,
+    names: &[sqlparser::ast::Ident],
+    columns: Vec<Column>,
+) -> SqlResult<Vec<Column>> {
+    if !names.is_empty() && names.len() != columns.len() {
+        return Err(SqlError::new(format!(
+            "{} has {} column names for {} columns",
+            table_name,
+            names.len(),
+            columns.len()
+        ))
+        .hint("name every selected column"));
+    }
+    Ok(columns
+        .into_iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let name = match names.get(i) {
+                Some(name) => name.value.clone(),
+                None => column.name,
+            };
+            Column {
+                full_name: full_column_name(Some(&table_name.to_string()), name.clone()),
+                name,
+                table_name: table_name.into(),
+                column_type: ColumnType::Column,
+                label: None,
+                ..column
+            }
+        })
+        .collect())
+}
+
+fn subquery_params(
+    table_columns: &[Column],
+    expr: &sqlparser::ast::Expr,
+) -> SqlResult<Vec<Column>> {
+    use sqlparser::ast::{Expr, FunctionArg, FunctionArgExpr};
+    let exprs: Vec<&Expr> = match expr {
+        Expr::Subquery(query) => return Ok(query_columns(table_columns, query)?.0),
+        Expr::Nested(expr) | Expr::UnaryOp { expr, .. } | Expr::Cast { expr, .. } => vec![expr],
+        Expr::BinaryOp { left, right, .. } => vec![left, right],
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => operand
+            .iter()
+            .map(|expr| &**expr)
+            .chain(conditions)
+            .chain(results)
+            .chain(else_result.iter().map(|expr| &**expr))
+            .collect(),
+        Expr::Function(function) => function
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                FunctionArg::Named {
+                    arg: FunctionArgExpr::Expr(expr),
+                    ..
+                }
+                | FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Some(expr),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
     };
 
-    Some(Stmt::Query {
-        ident,
-        sql,
-        in_cols,
-        out_cols,
-        ret,
-        cast,
-    })
+    Ok(exprs
+        .into_iter()
+        .map(|expr| subquery_params(table_columns, expr))
+        .collect::<SqlResult<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect())
 }
 
 fn update_stmt(
-    db_cols: &HashSet<Column>,
+    db_cols: &[Column],
     ident: Ident,
     sql: String,
     table: &TableWithJoins,
@@ -550,36 +1957,39 @@ fn update_stmt(
     selection: &Option<sqlparser::ast::Expr>,
     returning: &Option<Vec<SelectItem>>,
     cast: Cast,
-) -> Option<Stmt> {
+) -> SqlResult<Option<Stmt>> {
     let table_names = table_names(table);
-    let table_name = table_names.get(0);
-    let table_name = match table_name {
-        Some(t) => t,
-        None => panic!("update needs a table name in {}", &ident),
+    let table_name = match table_names.first() {
+        Some((t, _)) => t,
+        None => {
+            return Err(SqlError::new("update needs a table name")
+                .fragment(&table.relation)
+                .hint("update a table declared in this db! block"))
+        }
     };
 
     let table_columns = db_cols
         .iter()
         .filter(|c| &c.table_name == table_name)
         .map(|c| c.clone())
-        .collect::<HashSet<_>>();
-    let out_cols = match returning {
-        Some(si) => si
-            .iter()
-            .flat_map(|si| columns_from_select_item(&table_columns, si, &cast))
-            .collect::<Vec<_>>(),
-        None => vec![],
-    };
+        .collect::<Vec<_>>();
+    let out_cols = returning_columns(&table_columns, returning)?;
     let mut in_cols = assignments
         .iter()
-        .filter_map(|a| match a.value {
-            sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(_)) => Some(&a.id),
+        .filter_map(|a| match &a.value {
+            sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)) => {
+                Some((&a.id, token))
+            }
             _ => None,
         })
-        .flat_map(|c| columns_from_idents(&table_columns, c))
+        .flat_map(|(c, token)| {
+            columns_from_idents(&table_columns, c)
+                .into_iter()
+                .map(|c| c.placeholder(token))
+        })
         .collect::<Vec<_>>();
     in_cols.extend(match selection {
-        Some(expr) => columns_from_expr(&table_columns, expr, None),
+        Some(expr) => columns_from_expr(&table_columns, expr, None)?,
         None => vec![],
     });
 
@@ -587,103 +1997,436 @@ fn update_stmt(
         Some(_) => {
             if cast != Cast::None {
                 for tc in table_columns.iter() {
-                    if !out_cols.contains(&tc) {
-                        panic!(
-                            "in query {} column {} needs to be returned with table {} ",
-                            ident, tc.name, table_name
-                        )
+                    if !out_cols.contains(tc) {
+                        return Err(returning_error(&tc.name, table_name));
                     }
                 }
             }
 
-            Some(Stmt::Query {
+            // no row matched the where clause is Error::NotFound
+            Ok(Some(Stmt::Query {
                 ident,
                 sql,
                 in_cols,
                 out_cols,
                 ret: QueryReturn::Row,
                 cast,
-            })
+                read: false,
+            }))
         }
-        None => Some(Stmt::Execute {
+        None => Ok(Some(Stmt::Execute {
             ident,
             sql,
             in_cols,
+        })),
+    }
+}
+
+fn returning_columns(
+    table_columns: &[Column],
+    returning: &Option<Vec<SelectItem>>,
+) -> SqlResult<Vec<Column>> {
+    Ok(returning
+        .iter()
+        .flatten()
+        .map(|si| columns_from_select_item(table_columns, si))
+        .collect::<SqlResult<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+fn returning_error(column_name: &str, table_name: &str) -> SqlError {
+    SqlError::new(format!(
+        "column {} needs to be returned with table {}",
+        column_name, table_name
+    ))
+    .hint("use `returning *` or remove the `as T` cast")
+}
+
+fn to_statement_expr(sql_expr: SqlExpr) -> Result<SqlExpr> {
+    let statements = Parser::parse_sql(&SQLiteDialect {}, &parseable_sql(&sql_expr.sql))
+        .map_err(|err| syn::Error::new(sql_expr.span, format!("{}: {}", sql_expr.ident, err)))?;
+    let sql_expr = SqlExpr {
+        statements,
+        ..sql_expr
+    };
+
+    match sql_expr.triggers {
+        true => Ok(SqlExpr {
+            sql: fts5_triggers(&sql_expr)?,
+            ..sql_expr
         }),
+        false => Ok(sql_expr),
     }
 }
 
-fn to_statement_expr(
-    SqlExpr {
-        ident, sql, cast, ..
-    }: SqlExpr,
-) -> Option<SqlExpr> {
-    let statements = match Parser::parse_sql(&SQLiteDialect {}, &sql) {
-        Ok(ast) => ast,
-        Err(err) => {
-            // TODO: better error handling
-            panic!("{}", err);
-        }
+// sqlparser doesn't know `match` or fts5 options like `content='posts'` so statements are
+// parsed with `=` for `match` and only the column names of fts5 tables,
+// sqlite still gets the sql as written
+fn parseable_sql(sql: &str) -> String {
+    let Ok(tokens) = Tokenizer::new(&SQLiteDialect {}, sql).tokenize_with_location() else {
+        return sql.into();
     };
+    // only the rewritten tokens are spliced in, the rest keeps its quoting as written
+    let mut edits = vec![];
+    for t in &tokens {
+        match &t.token {
+            Token::Word(word) if word.keyword == Keyword::MATCH => {
+                if let Some(offset) = location_offset(sql, t.location) {
+                    edits.push((offset..offset + word.value.len(), "=".to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+    let plain = tokens.iter().map(|t| t.token.clone()).collect::<Vec<_>>();
+    if let Some((range, args)) = fts5_args(&plain) {
+        let columns = args
+            .iter()
+            .filter(|arg| !arg.contains(&&Token::Eq))
+            .filter_map(|arg| arg.first().map(|token| token.to_string()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let start = location_offset(sql, tokens[range.start - 1].location).map(|lparen| lparen + 1);
+        let end = location_offset(sql, tokens[range.end].location);
+        if let (Some(start), Some(end)) = (start, end) {
+            edits.push((start..end, columns));
+        }
+    }
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut sql = sql.to_string();
+    // later edits first so earlier offsets stay put
+    for (range, replacement) in edits.into_iter().rev() {
+        sql.replace_range(range, &replacement);
+    }
 
-    Some(SqlExpr {
-        ident,
-        sql,
-        statements,
-        cast,
-    })
+    sql
 }
 
-fn columns(SqlExpr { statements, .. }: &SqlExpr) -> HashSet<Column> {
-    statements
+// the args of `using fts5(...)` split on commas and the range of tokens between the parens
+fn fts5_args(tokens: &[Token]) -> Option<(std::ops::Range<usize>, Vec<Vec<&Token>>)> {
+    let significant = tokens
         .iter()
-        .filter_map(|statement| match statement {
-            Statement::CreateTable { name, columns, .. } => {
-                let name = name.to_string();
-                let columns = columns
-                    .iter()
-                    .map(|c| column(Some(&name), c))
-                    .collect::<Vec<_>>();
+        .enumerate()
+        .filter(|(_, t)| !matches!(t, Token::Whitespace(_)))
+        .collect::<Vec<_>>();
+    let start = significant.windows(3).find_map(|window| match window {
+        [(_, Token::Word(using)), (_, Token::Word(module)), (i, Token::LParen)]
+            if using.keyword == Keyword::USING && module.value.eq_ignore_ascii_case("fts5") =>
+        {
+            Some(i + 1)
+        }
+        _ => None,
+    })?;
+    let mut depth = 0;
+    let mut args = vec![vec![]];
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::RParen if depth == 0 => return Some((start..i, args)),
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Comma if depth == 0 => {
+                args.push(vec![]);
+                continue;
+            }
+            Token::Whitespace(_) => continue,
+            _ => {}
+        }
+        args.last_mut()?.push(token);
+    }
+
+    None
+}
+
+// #[triggers] on an external content fts5 table adds insert, update and delete triggers
+// to its content table that keep the index in sync
+fn fts5_triggers(def: &SqlExpr) -> Result<String> {
+    let error = |message: &str| syn::Error::new(def.span, format!("{}: {}", def.ident, message));
+    let Some((table, columns)) = def.statements.iter().find_map(|stmt| match stmt {
+        Statement::CreateVirtualTable {
+            name,
+            module_name,
+            module_args,
+            ..
+        } if is_fts5(module_name) => Some((
+            name.to_string(),
+            module_args
+                .iter()
+                .map(|arg| arg.value.clone())
+                .collect::<Vec<_>>(),
+        )),
+        _ => None,
+    }) else {
+        return Err(error(
+            "#[triggers] only works on create virtual table ... using fts5",
+        ));
+    };
+    let tokens = Tokenizer::new(&SQLiteDialect {}, &def.sql)
+        .tokenize()
+        .map_err(|err| error(&err.to_string()))?;
+    let args = fts5_args(&tokens).map(|(_, args)| args).unwrap_or_default();
+    let option = |key: &str| {
+        args.iter().find_map(|arg| match &arg[..] {
+            [Token::Word(name), Token::Eq, value] if name.value.eq_ignore_ascii_case(key) => {
+                match value {
+                    Token::SingleQuotedString(value) | Token::DoubleQuotedString(value) => {
+                        Some(value.clone())
+                    }
+                    Token::Word(word) => Some(word.value.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+    };
+    let Some(content) = option("content").filter(|content| !content.is_empty()) else {
+        return Err(error(
+            "#[triggers] needs a content table like `content='posts'`",
+        ));
+    };
+    let rowid = option("content_rowid").unwrap_or_else(|| "rowid".into());
+    let values = |prefix: &str| {
+        columns
+            .iter()
+            .map(|column| format!("{}.{}", prefix, column))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (columns, new, old) = (columns.join(", "), values("new"), values("old"));
+    let insert = format!("insert into {table} (rowid, {columns}) values (new.{rowid}, {new});");
+    let delete = format!(
+        "insert into {table} ({table}, rowid, {columns}) values ('delete', old.{rowid}, {old});"
+    );
+
+    Ok(format!(
+        "{};
+create trigger if not exists {table}_insert after insert on {content} begin
+    {insert}
+end;
+create trigger if not exists {table}_delete after delete on {content} begin
+    {delete}
+end;
+create trigger if not exists {table}_update after update on {content} begin
+    {delete}
+    {insert}
+end;",
+        def.sql.trim_end().trim_end_matches(';')
+    ))
+}
 
-                Some(columns)
+fn schema<'a>(defs: impl IntoIterator<Item = &'a SqlExpr>) -> Vec<Column> {
+    let mut cols: Vec<Column> = vec![];
+    // enums from check constraints are named after the table's struct
+    let mut structs: Vec<(String, Ident)> = vec![];
+    let statements = defs
+        .into_iter()
+        .flat_map(|def| def.statements.iter().map(move |stmt| (stmt, def)));
+    for (statement, def) in statements {
+        let types = &def.types;
+        match statement {
+            Statement::CreateTable {
+                name,
+                columns,
+                constraints,
+                ..
+            } => {
+                let name = name.to_string();
+                if !cols.iter().any(|c| c.table_name == name) {
+                    let struct_ident = match &def.cast {
+                        Cast::T(ident) | Cast::Vec(ident) => ident.clone(),
+                        Cast::None => struct_ident(&def.ident),
+                    };
+                    let table_checks = constraints
+                        .iter()
+                        .filter_map(|constraint| match constraint {
+                            TableConstraint::Check { expr, .. } => Some(&**expr),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    let primary_key = constraints.iter().find_map(|constraint| match constraint {
+                        TableConstraint::Unique {
+                            columns,
+                            is_primary: true,
+                            ..
+                        } => Some(columns),
+                        _ => None,
+                    });
+                    let foreign_key = |name: &sqlparser::ast::Ident| {
+                        constraints.iter().find_map(|constraint| match constraint {
+                            TableConstraint::ForeignKey {
+                                columns,
+                                foreign_table,
+                                referred_columns,
+                                ..
+                            } if columns.len() == 1 && columns[0] == *name => Some((
+                                foreign_table.to_string(),
+                                referred_columns.first().map(|c| c.value.clone()),
+                            )),
+                            _ => None,
+                        })
+                    };
+                    cols.extend(columns.iter().map(|c| {
+                        let column = column(Some(&name), c, types);
+                        let column = Column {
+                            primary_key: column.primary_key
+                                || primary_key.is_some_and(|pk| pk.contains(&c.name)),
+                            references: column.references.or_else(|| foreign_key(&c.name)),
+                            ..column
+                        };
+                        enum_column(column, c, &table_checks, &struct_ident)
+                    }));
+                    structs.push((name, struct_ident));
+                }
             }
             Statement::AlterTable {
                 name, operations, ..
             } => {
-                let name = name.to_string();
-                let columns = operations
-                    .iter()
-                    .filter_map(|op| match op {
+                let mut name = name.to_string();
+                for op in operations {
+                    match op {
                         AlterTableOperation::AddColumn { column_def, .. } => {
-                            Some(column(Some(&name), column_def))
+                            let column = column(Some(&name), column_def, types);
+                            cols.push(match structs.iter().find(|(table, _)| *table == name) {
+                                Some((_, struct_ident)) => {
+                                    enum_column(column, column_def, &[], struct_ident)
+                                }
+                                None => column,
+                            })
                         }
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>();
-
-                Some(columns)
+                        AlterTableOperation::DropColumn { column_name, .. } => {
+                            cols.retain(|c| !(c.table_name == name && c.name == column_name.value))
+                        }
+                        AlterTableOperation::RenameColumn {
+                            old_column_name,
+                            new_column_name,
+                        } => cols
+                            .iter_mut()
+                            .filter(|c| c.table_name == name && c.name == old_column_name.value)
+                            .for_each(|c| {
+                                c.name = new_column_name.value.clone();
+                                c.full_name = full_column_name(Some(&name), c.name.clone());
+                            }),
+                        AlterTableOperation::RenameTable { table_name } => {
+                            let new_name = table_name.to_string();
+                            structs
+                                .iter_mut()
+                                .filter(|(table, _)| *table == name)
+                                .for_each(|(table, _)| *table = new_name.clone());
+                            cols.iter_mut()
+                                .filter(|c| c.table_name == name)
+                                .for_each(|c| {
+                                    c.table_name = new_name.clone();
+                                    c.full_name = full_column_name(Some(&new_name), c.name.clone());
+                                });
+                            name = new_name;
+                        }
+                        _ => {}
+                    }
+                }
             }
-            _ => None,
-        })
-        .flat_map(|c| c)
-        .collect()
+            Statement::CreateVirtualTable {
+                name,
+                module_name,
+                module_args,
+                ..
+            } if is_fts5(module_name) => {
+                let name = name.to_string();
+                if !cols.iter().any(|c| c.table_name == name) {
+                    let fts_column = |column_name: &str, data_type, hidden| Column {
+                        name: column_name.into(),
+                        full_name: full_column_name(Some(&name), column_name.into()),
+                        table_name: name.clone(),
+                        data_type,
+                        hidden,
+                        ..Default::default()
+                    };
+                    cols.extend(
+                        module_args
+                            .iter()
+                            .map(|arg| fts_column(&arg.value, DataType::Text, false)),
+                    );
+                    // `where posts_fts match ?`, `order by rank` and joins on rowid
+                    cols.extend([
+                        fts_column(&name, DataType::Text, true),
+                        fts_column("rank", DataType::Real, true),
+                        fts_column("rowid", DataType::Integer, true),
+                    ]);
+                }
+            }
+            Statement::CreateView {
+                name,
+                columns,
+                query,
+                ..
+            } => {
+                let name = name.to_string();
+                if !cols.iter().any(|c| c.table_name == name) {
+                    // a view's columns are whatever its select returns, to_stmt reports
+                    // the error if that can't be typed
+                    let names = columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+                    let view_cols = query_columns(&cols, query)
+                        .and_then(|(_, view_cols)| alias_columns(&name, &names, view_cols));
+                    if let Ok(view_cols) = view_cols {
+                        cols.extend(view_cols.into_iter().map(|c| Column {
+                            primary_key: false,
+                            references: None,
+                            default: None,
+                            hidden: false,
+                            ..c
+                        }));
+                    }
+                }
+            }
+            Statement::Drop {
+                object_type: ObjectType::Table | ObjectType::View,
+                names,
+                ..
+            } => cols.retain(|c| !names.iter().any(|n| n.to_string() == c.table_name)),
+            _ => {}
+        }
+    }
+
+    cols
+}
+
+fn is_fts5(module_name: &sqlparser::ast::Ident) -> bool {
+    module_name.value.eq_ignore_ascii_case("fts5")
 }
 
-fn table_names(table: &TableWithJoins) -> Vec<String> {
+// table names paired with whether an outer join can make their columns null
+fn table_names(table: &TableWithJoins) -> Vec<(String, bool)> {
     let mut results = table_names_from(&table.relation);
-    results.extend(
-        table
-            .joins
-            .iter()
-            .flat_map(|j| table_names_from(&j.relation)),
-    );
+    for join in &table.joins {
+        let (left_nullable, right_nullable) = join_nullability(&join.join_operator);
+        if left_nullable {
+            results
+                .iter_mut()
+                .for_each(|(_, nullable)| *nullable = true);
+        }
+        results.extend(
+            table_names_from(&join.relation)
+                .into_iter()
+                .map(|(name, nullable)| (name, nullable || right_nullable)),
+        );
+    }
 
     results
 }
 
-fn table_names_from(relation: &TableFactor) -> Vec<String> {
+// whether an outer join can make the left and the right side null
+fn join_nullability(join_operator: &JoinOperator) -> (bool, bool) {
+    match join_operator {
+        JoinOperator::LeftOuter(_) => (false, true),
+        JoinOperator::RightOuter(_) => (true, false),
+        JoinOperator::FullOuter(_) => (true, true),
+        _ => (false, false),
+    }
+}
+
+fn table_names_from(relation: &TableFactor) -> Vec<(String, bool)> {
     match relation {
-        sqlparser::ast::TableFactor::Table { name, .. } => vec![name.to_string()],
+        sqlparser::ast::TableFactor::Table { name, .. } => vec![(name.to_string(), false)],
         sqlparser::ast::TableFactor::NestedJoin {
             table_with_joins, ..
         } => table_names(&table_with_joins),
@@ -696,7 +2439,7 @@ fn struct_ident(ident: &Ident) -> Ident {
 }
 
 fn insert_stmt(
-    db_cols: &HashSet<Column>,
+    db_cols: &[Column],
     ident: Ident,
     sql: String,
     table_name: String,
@@ -705,41 +2448,31 @@ fn insert_stmt(
     source: &Option<Box<Query>>,
     cast: Cast,
     on: &Option<sqlparser::ast::OnInsert>,
-) -> Option<Stmt> {
-    // nice little compile time validation
-    // check insert into count matches placeholder count
-    let placeholder_count = match source.as_deref() {
+) -> SqlResult<Option<Stmt>> {
+    let rows = match source.as_deref() {
         Some(Query { body, .. }) => match &**body {
-            SetExpr::Values(value) => Some(
-                value
-                    .rows
-                    .iter()
-                    .flatten()
-                    .filter(|expr| match expr {
-                        sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(_)) => true,
-                        _ => false,
-                    })
-                    .count(),
-            ),
-            _ => None,
+            SetExpr::Values(values) => values.rows.as_slice(),
+            _ => &[],
         },
-        None => None,
+        None => &[],
     };
     let input_col_names = columns.iter().map(|c| c.to_string()).collect::<Vec<_>>();
-    match placeholder_count {
-        Some(pc) => {
-            if pc != input_col_names.len() {
-                panic!("{} placeholder count doesn't match insert into", ident);
-            }
-        }
-        None => {}
+    // nice little compile time validation
+    // check insert into count matches values count
+    if let Some(row) = rows.iter().find(|row| row.len() != input_col_names.len()) {
+        return Err(SqlError::new(format!(
+            "{} values for {} insert columns",
+            row.len(),
+            input_col_names.len()
+        ))
+        .hint("use one value per column in the insert column list"));
     }
 
     let table_columns = db_cols
         .iter()
         .filter(|c| c.table_name == table_name)
         .map(|c| c.clone())
-        .collect::<HashSet<_>>();
+        .collect::<Vec<_>>();
 
     // check insert into matches table columns
     let table_column_names = table_columns
@@ -748,33 +2481,39 @@ fn insert_stmt(
         .collect::<Vec<_>>();
     for n in input_col_names {
         if !table_column_names.contains(&n) {
-            panic!("column {} does not exist in table {}", n, table_name);
+            return Err(SqlError::new(format!(
+                "column {} does not exist in table {}",
+                n, table_name
+            )));
         }
     }
 
-    let in_cols: Vec<Column> = columns_from_idents(&table_columns, columns);
+    let in_cols = rows
+        .iter()
+        .flat_map(|row| columns.iter().zip(row))
+        .filter_map(|(column, expr)| match expr {
+            sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)) => {
+                Some((column, token))
+            }
+            _ => None,
+        })
+        .flat_map(|(column, token)| {
+            columns_from_idents(&table_columns, &vec![column.clone()])
+                .into_iter()
+                .map(|c| {
+                    let has_default = c.has_default();
+                    c.nullable(has_default).placeholder(token)
+                })
+        })
+        .collect::<Vec<_>>();
 
     match returning {
         Some(_) => {
-            let out_cols: Vec<Column> = match returning {
-                Some(si) => si
-                    .iter()
-                    .flat_map(|si| columns_from_select_item(&table_columns, si, &cast))
-                    .collect(),
-                None => vec![],
-            };
+            let out_cols = returning_columns(&table_columns, returning)?;
             if cast != Cast::None {
                 for n in table_column_names.iter() {
-                    if !out_cols
-                        .iter()
-                        .map(|c| &c.name)
-                        .collect::<Vec<_>>()
-                        .contains(&n)
-                    {
-                        panic!(
-                            "in query {} column {} needs to be returned with table {} ",
-                            ident, n, table_name
-                        )
+                    if !out_cols.iter().any(|c| &c.name == n) {
+                        return Err(returning_error(n, &table_name));
                     }
                 }
             }
@@ -786,88 +2525,79 @@ fn insert_stmt(
                 Some(_) | None => QueryReturn::Row,
             };
 
-            Some(Stmt::Query {
+            Ok(Some(Stmt::Query {
                 ident,
                 sql,
                 in_cols,
                 out_cols,
                 ret,
                 cast,
-            })
+                read: false,
+            }))
         }
-        None => Some(Stmt::Execute {
+        None => Ok(Some(Stmt::Execute {
             ident,
             sql,
             in_cols,
-        }),
+        })),
     }
 }
 
 fn delete_stmt(
-    db_cols: &HashSet<Column>,
+    db_cols: &[Column],
     ident: Ident,
     sql: String,
     from: &Vec<TableWithJoins>,
     selection: &Option<sqlparser::ast::Expr>,
     returning: &Option<Vec<SelectItem>>,
     cast: Cast,
-) -> Option<Stmt> {
-    let table_names = from.iter().flat_map(|f| table_names(f)).collect::<Vec<_>>();
+) -> SqlResult<Option<Stmt>> {
+    let table_names = from.iter().flat_map(table_names).collect::<Vec<_>>();
     let table_name = match table_names.first() {
-        Some(t) => t.to_string(),
-        None => panic!("delete expects table name {}", &ident),
+        Some((t, _)) => t.to_string(),
+        None => {
+            return Err(SqlError::new("delete expects a table name")
+                .hint("delete from a table declared in this db! block"))
+        }
     };
     let table_columns = db_cols
         .iter()
         .filter(|c| c.table_name == table_name)
         .map(|c| c.clone())
-        .collect::<HashSet<_>>();
+        .collect::<Vec<_>>();
     let in_cols = match selection {
-        Some(expr) => columns_from_expr(&table_columns, expr, None),
-        None => vec![],
-    };
-    let out_cols = match returning {
-        Some(si) => si
-            .iter()
-            .flat_map(|si| columns_from_select_item(&table_columns, si, &cast))
-            .collect::<Vec<_>>(),
+        Some(expr) => columns_from_expr(&table_columns, expr, None)?,
         None => vec![],
     };
+    let out_cols = returning_columns(&table_columns, returning)?;
 
     match returning {
         Some(_) => {
             match &cast {
                 Cast::T(_) | Cast::Vec(_) => {
                     for n in table_columns.iter() {
-                        if !out_cols
-                            .iter()
-                            .map(|c| &c.name)
-                            .collect::<Vec<_>>()
-                            .contains(&&n.name)
-                        {
-                            panic!(
-                                "in query {} column {} needs to be returned with table {} ",
-                                ident, n.name, table_name
-                            )
+                        if !out_cols.iter().any(|c| c.name == n.name) {
+                            return Err(returning_error(&n.name, &table_name));
                         }
                     }
                 }
                 Cast::None => {}
             }
-            Some(Stmt::Query {
+            Ok(Some(Stmt::Query {
                 ident,
                 sql,
                 in_cols,
                 out_cols,
                 ret: QueryReturn::OptionRow,
                 cast,
-            })
+                read: false,
+            }))
         }
-        _ => Some(Stmt::Execute {
+        _ => Ok(Some(Stmt::Execute {
             ident,
             sql,
             in_cols,
-        }),
+        })),
     }
 }
 
@@ -896,10 +2626,49 @@ struct Column {
     table_name: String,
     column_type: ColumnType,
     data_type: DataType,
+    placeholder: Option<String>,
+    label: Option<String>,
+    default: Option<sqlparser::ast::Expr>,
+    primary_key: bool,
+    // foreign table and column, no column means its primary key
+    references: Option<(String, Option<String>)>,
+    arg: Option<Arg>,
+    // `in (?)` params take a vec
+    list: bool,
+    // fts5 tables have rowid, rank and a column named after the table that `select *` leaves out
+    hidden: bool,
+}
+
+// relation helpers take rows instead of ids, params are read from their fields
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Arg {
+    Row(Ident, String),
+    Rows(Ident, String),
+}
+
+impl Column {
+    fn nullable(self, nullable: bool) -> Self {
+        Self {
+            data_type: self.data_type.nullable(nullable),
+            ..self
+        }
+    }
+
+    // not null columns with a default can be left out of inserts
+    fn has_default(&self) -> bool {
+        self.default.is_some() && !self.data_type.is_null()
+    }
+
+    fn placeholder(self, token: &str) -> Self {
+        Self {
+            placeholder: Some(token.into()),
+            ..self
+        }
+    }
 }
 
 fn columns_from_idents(
-    table_columns: &HashSet<Column>,
+    table_columns: &[Column],
     column_names: &Vec<sqlparser::ast::Ident>,
 ) -> Vec<Column> {
     column_names
@@ -914,65 +2683,160 @@ fn columns_from_idents(
 }
 
 fn columns_from_select_item(
-    table_columns: &HashSet<Column>,
+    table_columns: &[Column],
     select_item: &sqlparser::ast::SelectItem,
-    cast: &Cast,
-) -> HashSet<Column> {
+) -> SqlResult<Vec<Column>> {
     match select_item {
-        sqlparser::ast::SelectItem::UnnamedExpr(expr) => {
-            columns_from_expr(&table_columns, expr, None)
-                .into_iter()
-                .collect::<HashSet<_>>()
+        sqlparser::ast::SelectItem::UnnamedExpr(
+            expr @ (sqlparser::ast::Expr::Identifier(_)
+            | sqlparser::ast::Expr::CompoundIdentifier(_)
+            | sqlparser::ast::Expr::QualifiedWildcard(_)),
+        ) => columns_from_expr(table_columns, expr, None),
+        sqlparser::ast::SelectItem::UnnamedExpr(expr) if function_name(expr) == "count" => {
+            columns_from_expr(table_columns, expr, None)
         }
+        sqlparser::ast::SelectItem::UnnamedExpr(expr) => Ok(vec![Column {
+            name: expr.to_string(),
+            full_name: expr.to_string(),
+            data_type: expr_type(table_columns, expr)?,
+            ..Default::default()
+        }]),
         sqlparser::ast::SelectItem::ExprWithAlias { expr, alias } => {
-            columns_from_expr(&table_columns, expr, Some(alias))
-                .into_iter()
-                .collect::<HashSet<_>>()
+            // `as "name?"` forces a nullable field and `as "name!"` a required one
+            let data_type = expr_type(table_columns, expr)?;
+            let (name, data_type) =
+                match (alias.value.strip_suffix('?'), alias.value.strip_suffix('!')) {
+                    (Some(name), _) => (name, data_type.nullable(true)),
+                    (_, Some(name)) => (name, data_type.inner().clone()),
+                    _ => (alias.value.as_str(), data_type),
+                };
+            let column_expr = matches!(
+                expr,
+                sqlparser::ast::Expr::Identifier(_) | sqlparser::ast::Expr::CompoundIdentifier(_)
+            );
+            if !column_expr && data_type.inner() == &DataType::Any {
+                return Err(SqlError::new(format!("cannot infer the type of {}", alias))
+                    .fragment(expr)
+                    .hint("wrap it in `cast(... as integer)`, `real`, `text` or `blob`"));
+            }
+            let column_type = match function_name(expr).as_str() {
+                "count" => ColumnType::Aggregate,
+                _ => ColumnType::Column,
+            };
+
+            Ok(vec![Column {
+                name: name.into(),
+                full_name: name.into(),
+                label: Some(alias.value.clone()),
+                data_type,
+                column_type,
+                ..Default::default()
+            }])
         }
-        sqlparser::ast::SelectItem::QualifiedWildcard(obj_name, _) => table_columns
+        sqlparser::ast::SelectItem::QualifiedWildcard(obj_name, _) => Ok(table_columns
             .iter()
-            .filter(|c| c.table_name == obj_name.to_string())
-            .map(|c| c.clone())
-            .collect::<HashSet<_>>(),
-        sqlparser::ast::SelectItem::Wildcard(_) => match cast {
-            Cast::T(_) | Cast::Vec(_) => table_columns.clone(),
-            Cast::None => todo!("unqualified * not supported yet"),
-        },
+            .filter(|c| c.table_name == obj_name.to_string() && !c.hidden)
+            .cloned()
+            .collect::<Vec<_>>()),
+        sqlparser::ast::SelectItem::Wildcard(_) => Ok(table_columns
+            .iter()
+            .filter(|c| !c.hidden)
+            .cloned()
+            .collect::<Vec<_>>()),
     }
 }
 
-fn in_columns_from_query(
-    table_columns: &HashSet<Column>,
-    query: &sqlparser::ast::Query,
-) -> Vec<Column> {
-    let sqlparser::ast::Query { body, .. } = query;
-    match &**body {
-        SetExpr::Select(select) => {
-            let Select { selection, .. } = &**select;
-            match selection {
-                Some(expr) => columns_from_expr(table_columns, expr, None),
-                None => todo!(),
+fn placeholder_columns(
+    table_columns: &[Column],
+    expr: &sqlparser::ast::Expr,
+    token: &str,
+) -> SqlResult<Vec<Column>> {
+    Ok(columns_from_expr(table_columns, expr, None)?
+        .into_iter()
+        .map(|c| c.placeholder(token))
+        .collect())
+}
+
+// numbers placeholders the way sqlite does, one argument per distinct parameter
+fn params(in_cols: Vec<Column>) -> SqlResult<Vec<Column>> {
+    let mut params: Vec<(usize, Column)> = vec![];
+    for column in in_cols {
+        let token = column.placeholder.clone().unwrap_or_else(|| "?".into());
+        let max = params.iter().map(|(index, _)| *index).max().unwrap_or(0);
+        let (index, name) = match token.split_at(1) {
+            ("?", "") => (max + 1, column.name.clone()),
+            ("?", number) => match number.parse::<usize>() {
+                Ok(index) if index > 0 => (index, column.name.clone()),
+                _ => {
+                    return Err(SqlError::new(format!("invalid placeholder {}", token))
+                        .hint("numbered placeholders look like `?1`"))
+                }
+            },
+            (":" | "@" | "$", name) => match params
+                .iter()
+                .find(|(_, c)| c.placeholder.as_ref() == Some(&token))
+            {
+                Some((index, _)) => (*index, name.to_string()),
+                None => (max + 1, name.to_string()),
+            },
+            _ => {
+                return Err(SqlError::new(format!("unsupported placeholder {}", token))
+                    .hint("use `?`, `?NNN`, `:name`, `@name` or `$name` placeholders"))
             }
+        };
+        if syn::parse_str::<Ident>(&name).is_err() {
+            return Err(
+                SqlError::new(format!("placeholder {} is not a valid rust name", token))
+                    .hint("name placeholders with letters, numbers and underscores"),
+            );
+        }
+        if !params.iter().any(|(i, _)| *i == index) {
+            params.push((index, Column { name, ..column }));
         }
-        _ => todo!(),
     }
+    params.sort_by_key(|(index, _)| *index);
+    if let Some(missing) = (1..)
+        .zip(&params)
+        .find_map(|(expected, (index, _))| (expected != *index).then_some(expected))
+    {
+        return Err(
+            SqlError::new(format!("placeholder ?{} is never used", missing))
+                .hint("number placeholders from ?1 without gaps"),
+        );
+    }
+    // the same column compared twice needs two distinct argument names
+    let mut names: Vec<String> = vec![];
+    Ok(params
+        .into_iter()
+        .map(|(_, column)| {
+            let count = names.iter().filter(|n| **n == column.name).count();
+            names.push(column.name.clone());
+            match count {
+                0 => column,
+                n => Column {
+                    name: format!("{}_{}", column.name, n + 1),
+                    ..column
+                },
+            }
+        })
+        .collect())
 }
 
 fn columns_from_expr(
-    table_columns: &HashSet<Column>,
+    table_columns: &[Column],
     expr: &sqlparser::ast::Expr,
     alias: Option<&sqlparser::ast::Ident>,
-) -> Vec<Column> {
-    match expr {
+) -> SqlResult<Vec<Column>> {
+    let columns = match expr {
         sqlparser::ast::Expr::Identifier(ident) => {
-            match table_columns.iter().find(|c| c.name == ident.to_string()) {
+            match table_columns.iter().find(|c| c.name == ident.value) {
                 Some(c) => vec![c.clone()],
-                None => panic!("column {} does not exist", ident.to_string()),
+                None => return Err(SqlError::new(format!("column {} does not exist", ident))),
             }
         }
         sqlparser::ast::Expr::CompoundIdentifier(idents) => {
             let name = idents
-                .into_iter()
+                .iter()
                 .map(|ident| ident.value.clone())
                 .collect::<Vec<_>>()
                 .join(".");
@@ -981,14 +2845,15 @@ fn columns_from_expr(
                 None => vec![],
             }
         }
-        sqlparser::ast::Expr::Wildcard => {
-            panic!("unqualified * not supported yet");
-            // table_columns.iter().map(|c| c.clone()).collect::<Vec<_>>()
-        }
+        sqlparser::ast::Expr::Wildcard => table_columns
+            .iter()
+            .filter(|c| !c.hidden)
+            .cloned()
+            .collect::<Vec<_>>(),
         sqlparser::ast::Expr::QualifiedWildcard(obj_name) => table_columns
             .iter()
-            .filter(|c| c.table_name == obj_name.to_string())
-            .map(|c| c.clone())
+            .filter(|c| c.table_name == obj_name.to_string() && !c.hidden)
+            .cloned()
             .collect::<Vec<_>>(),
         sqlparser::ast::Expr::BinaryOp { left, right, .. } => match (&**left, &**right) {
             (
@@ -998,26 +2863,51 @@ fn columns_from_expr(
             | (
                 sqlparser::ast::Expr::CompoundIdentifier(_),
                 sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)),
-            ) => match token.as_str() {
-                "?" => columns_from_expr(&table_columns, left, None),
-                _ => unimplemented!("? placeholders only please"),
-            },
-            (sqlparser::ast::Expr::BinaryOp { .. }, sqlparser::ast::Expr::BinaryOp { .. }) => vec![
-                columns_from_expr(&table_columns, left, None),
-                columns_from_expr(&table_columns, right, None),
-            ]
-            .into_iter()
-            .flatten()
-            .collect(),
-            (sqlparser::ast::Expr::BinaryOp { .. }, _) => {
-                columns_from_expr(&table_columns, left, None)
-            }
-            (_, sqlparser::ast::Expr::BinaryOp { .. }) => {
-                columns_from_expr(&table_columns, right, None)
+            ) => placeholder_columns(table_columns, left, token)?,
+            (
+                sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)),
+                sqlparser::ast::Expr::Identifier(_),
+            )
+            | (
+                sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)),
+                sqlparser::ast::Expr::CompoundIdentifier(_),
+            ) => placeholder_columns(table_columns, right, token)?,
+            // `count(*) > ?` takes the type of the other side
+            (expr, sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)))
+            | (sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)), expr) => {
+                let name = match function_name(expr) {
+                    name if name.is_empty() => "value".to_string(),
+                    name => name,
+                };
+                vec![Column {
+                    full_name: name.clone(),
+                    name,
+                    data_type: expr_type(table_columns, expr)?,
+                    placeholder: Some(token.clone()),
+                    ..Default::default()
+                }]
             }
-            _ => vec![],
+            _ => [left, right]
+                .into_iter()
+                .filter(|expr| {
+                    !matches!(
+                        &***expr,
+                        sqlparser::ast::Expr::Identifier(_)
+                            | sqlparser::ast::Expr::CompoundIdentifier(_)
+                    )
+                })
+                .map(|expr| columns_from_expr(table_columns, expr, None))
+                .collect::<SqlResult<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                // functions like `count(*) > 1` in having aren't params
+                .filter(|c| c.placeholder.is_some())
+                .collect(),
         },
-        sqlparser::ast::Expr::Value(_) => vec![],
+        sqlparser::ast::Expr::Nested(expr) => columns_from_expr(table_columns, expr, None)?,
+        sqlparser::ast::Expr::Value(_)
+        | sqlparser::ast::Expr::IsNull(_)
+        | sqlparser::ast::Expr::IsNotNull(_) => vec![],
         sqlparser::ast::Expr::Function(sqlparser::ast::Function { name, args, .. }) => {
             match name.to_string().as_str() {
                 "count" => {
@@ -1026,20 +2916,22 @@ fn columns_from_expr(
                         .map(|fa| match fa {
                             sqlparser::ast::FunctionArg::Unnamed(fa_expr) => match fa_expr {
                                 sqlparser::ast::FunctionArgExpr::Expr(expr) => {
-                                    columns_from_expr(&table_columns, expr, None)
+                                    Ok(columns_from_expr(table_columns, expr, None)?
                                         .iter()
                                         .map(|c| c.full_name.clone())
                                         .collect::<Vec<_>>()
-                                        .join("")
+                                        .join(""))
                                 }
                                 sqlparser::ast::FunctionArgExpr::QualifiedWildcard(table_name) => {
-                                    format!("{}({}.*)", name, table_name)
+                                    Ok(format!("{}({}.*)", name, table_name))
                                 }
-                                sqlparser::ast::FunctionArgExpr::Wildcard => name.to_string(),
+                                sqlparser::ast::FunctionArgExpr::Wildcard => Ok(name.to_string()),
                             },
-                            _ => todo!("count"),
+                            fa => Err(SqlError::new("unsupported count argument")
+                                .fragment(fa)
+                                .hint("use `count(*)` or `count(column)`")),
                         })
-                        .collect::<Vec<_>>()
+                        .collect::<SqlResult<Vec<_>>>()?
                         .join("");
                     let name = match alias {
                         Some(name) => name.to_string(),
@@ -1051,6 +2943,7 @@ fn columns_from_expr(
                         table_name: "".into(),
                         data_type: DataType::Integer,
                         column_type: ColumnType::Aggregate,
+                        ..Default::default()
                     }]
                 }
                 "strftime" => vec![],
@@ -1066,18 +2959,248 @@ fn columns_from_expr(
                         table_name: "".into(),
                         data_type: DataType::Text,
                         column_type: ColumnType::Column,
+                        ..Default::default()
                     }]
                 }
-                _ => todo!("what"),
+                name => {
+                    return Err(SqlError::new(format!("unsupported function {}", name))
+                        .fragment(expr)
+                        .hint("supported functions are count, substr, strftime and unixepoch"))
+                }
+            }
+        }
+        sqlparser::ast::Expr::InList {
+            expr: column, list, ..
+        } => match &list[..] {
+            [sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token))] => {
+                placeholder_columns(table_columns, column, token)?
+                    .into_iter()
+                    .map(|c| match c.data_type.inner() {
+                        DataType::Blob | DataType::Any => Err(SqlError::new(format!(
+                            "{} is a blob and blobs can't be bound as a list",
+                            c.name
+                        ))
+                        .fragment(expr)
+                        .hint("lists are json arrays so only integers, reals and text fit")),
+                        _ => Ok(Column { list: true, ..c }),
+                    })
+                    .collect::<SqlResult<Vec<_>>>()?
             }
+            _ => list
+                .iter()
+                .map(|item| match item {
+                    sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)) => {
+                        placeholder_columns(table_columns, column, token)
+                    }
+                    sqlparser::ast::Expr::Identifier(_)
+                    | sqlparser::ast::Expr::CompoundIdentifier(_) => Ok(vec![]),
+                    item => columns_from_expr(table_columns, item, None),
+                })
+                .collect::<SqlResult<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect(),
+        },
+        sqlparser::ast::Expr::InSubquery { subquery, .. }
+        | sqlparser::ast::Expr::Exists { subquery, .. }
+        | sqlparser::ast::Expr::Subquery(subquery) => query_columns(table_columns, subquery)?.0,
+        sqlparser::ast::Expr::Between {
+            expr: column,
+            low,
+            high,
+            ..
+        } => [low, high]
+            .into_iter()
+            .map(|bound| match &**bound {
+                sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(token)) => {
+                    placeholder_columns(table_columns, column, token)
+                }
+                _ => Ok(vec![]),
+            })
+            .collect::<SqlResult<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect(),
+        expr => {
+            return Err(SqlError::new("unsupported expression").fragment(expr).hint(
+                "use columns, placeholders, comparisons, between, and/or, `in (?)` and `in (select ...)`",
+            ))
         }
-        sqlparser::ast::Expr::InSubquery { subquery, .. } => {
-            in_columns_from_query(table_columns, subquery)
+    };
+
+    Ok(columns)
+}
+
+fn function_name(expr: &sqlparser::ast::Expr) -> String {
+    match expr {
+        sqlparser::ast::Expr::Function(sqlparser::ast::Function { name, .. }) => {
+            name.to_string().to_lowercase()
         }
-        _ => todo!("columns_from_expr"),
+        _ => "".into(),
     }
 }
 
+// infers the sqlite type and nullability of a select list expression
+fn expr_type(table_columns: &[Column], expr: &sqlparser::ast::Expr) -> SqlResult<DataType> {
+    use sqlparser::ast::{BinaryOperator, Expr, UnaryOperator, Value};
+    let data_type = match expr {
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => {
+            match columns_from_expr(table_columns, expr, None)?.pop() {
+                Some(column) => column.data_type,
+                None => return Err(SqlError::new(format!("column {} does not exist", expr))),
+            }
+        }
+        Expr::Value(value) => match value {
+            Value::Number(number, _) if number.contains(['.', 'e', 'E']) => DataType::Real,
+            Value::Number(..) | Value::Boolean(_) => DataType::Integer,
+            Value::SingleQuotedString(_) | Value::DoubleQuotedString(_) => DataType::Text,
+            Value::HexStringLiteral(_) => DataType::Blob,
+            _ => DataType::Null(DataType::Any.into()),
+        },
+        Expr::Nested(expr) => expr_type(table_columns, expr)?,
+        Expr::UnaryOp { op, expr } => {
+            let data_type = expr_type(table_columns, expr)?;
+            match op {
+                UnaryOperator::Not => DataType::Integer.nullable(data_type.is_null()),
+                _ => data_type,
+            }
+        }
+        Expr::BinaryOp { left, op, right } => {
+            let left = expr_type(table_columns, left)?;
+            let right = expr_type(table_columns, right)?;
+            let nullable = left.is_null() || right.is_null();
+            let data_type = match op {
+                BinaryOperator::Plus
+                | BinaryOperator::Minus
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide
+                | BinaryOperator::Modulo => match (left.inner(), right.inner()) {
+                    (DataType::Integer, DataType::Integer) => DataType::Integer,
+                    (DataType::Real | DataType::Integer, DataType::Real | DataType::Integer) => {
+                        DataType::Real
+                    }
+                    _ => DataType::Any,
+                },
+                BinaryOperator::StringConcat => DataType::Text,
+                _ => DataType::Integer,
+            };
+            data_type.nullable(nullable)
+        }
+        Expr::IsNull(_)
+        | Expr::IsNotNull(_)
+        | Expr::IsTrue(_)
+        | Expr::IsFalse(_)
+        | Expr::IsNotTrue(_)
+        | Expr::IsNotFalse(_)
+        | Expr::IsDistinctFrom(..)
+        | Expr::IsNotDistinctFrom(..)
+        | Expr::Exists { .. } => DataType::Integer,
+        Expr::Between { expr, .. }
+        | Expr::InList { expr, .. }
+        | Expr::InSubquery { expr, .. }
+        | Expr::Like { expr, .. }
+        | Expr::ILike { expr, .. }
+        | Expr::SimilarTo { expr, .. } => {
+            DataType::Integer.nullable(expr_type(table_columns, expr)?.is_null())
+        }
+        Expr::Cast {
+            expr, data_type, ..
+        } => cast_type(data_type).nullable(expr_type(table_columns, expr)?.is_null()),
+        Expr::Case {
+            results,
+            else_result,
+            ..
+        } => {
+            let else_type = match else_result {
+                Some(expr) => expr_type(table_columns, expr)?,
+                None => DataType::Null(DataType::Any.into()),
+            };
+            results
+                .iter()
+                .map(|expr| expr_type(table_columns, expr))
+                .collect::<SqlResult<Vec<_>>>()?
+                .into_iter()
+                .fold(else_type, |acc, data_type| acc.union(&data_type))
+        }
+        Expr::JsonAccess { .. } => DataType::Null(DataType::Any.into()),
+        Expr::Subquery(query) => match &query_columns(table_columns, query)?.1[..] {
+            [column] => column.data_type.clone().nullable(true),
+            _ => {
+                return Err(SqlError::new("scalar subqueries select exactly one column")
+                    .fragment(expr)
+                    .hint("select a single column or use `exists (...)`"))
+            }
+        },
+        Expr::Function(sqlparser::ast::Function { args, .. }) => {
+            let args = args
+                .iter()
+                .map(|arg| match arg {
+                    sqlparser::ast::FunctionArg::Named { arg, .. }
+                    | sqlparser::ast::FunctionArg::Unnamed(arg) => match arg {
+                        sqlparser::ast::FunctionArgExpr::Expr(expr) => {
+                            expr_type(table_columns, expr)
+                        }
+                        _ => Ok(DataType::Integer),
+                    },
+                })
+                .collect::<SqlResult<Vec<_>>>()?;
+            let nullable = args.iter().any(|arg| arg.is_null());
+            let first = args.first().cloned().unwrap_or(DataType::Any);
+            match function_name(expr).as_str() {
+                "count" | "random" | "changes" | "total_changes" | "last_insert_rowid" => {
+                    DataType::Integer
+                }
+                "total" | "bm25" => DataType::Real,
+                "typeof" | "quote" | "json_object" | "json_array" | "json_group_array"
+                | "json_group_object" | "highlight" | "snippet" => DataType::Text,
+                "sum" | "min" | "max" if args.len() == 1 => first.nullable(true),
+                "avg" => DataType::Null(DataType::Real.into()),
+                "min" | "max" => args
+                    .iter()
+                    .fold(first.clone(), |acc, data_type| acc.union(data_type)),
+                "coalesce" | "ifnull" => {
+                    let data_type = args.iter().fold(first.inner().clone(), |acc, data_type| {
+                        acc.union(data_type.inner())
+                    });
+                    data_type.nullable(args.iter().all(|arg| arg.is_null()))
+                }
+                "nullif" => first.nullable(true),
+                "iif" => args
+                    .iter()
+                    .skip(1)
+                    .fold(args.get(1).cloned().unwrap_or(DataType::Any), |acc, dt| {
+                        acc.union(dt)
+                    }),
+                "abs" => first,
+                "length" | "octet_length" | "instr" | "unicode" | "sign" => {
+                    DataType::Integer.nullable(nullable)
+                }
+                "round" | "ceil" | "ceiling" | "floor" | "trunc" | "sqrt" | "pow" | "power"
+                | "exp" | "ln" | "log" | "log2" | "log10" | "pi" => {
+                    DataType::Real.nullable(nullable)
+                }
+                "lower" | "upper" | "trim" | "ltrim" | "rtrim" | "replace" | "substr"
+                | "substring" | "printf" | "format" | "hex" | "char" | "concat" | "concat_ws"
+                | "json" => DataType::Text.nullable(nullable),
+                "group_concat" | "string_agg" | "date" | "time" | "datetime" | "strftime" => {
+                    DataType::Null(DataType::Text.into())
+                }
+                "unixepoch" => DataType::Null(DataType::Integer.into()),
+                "julianday" => DataType::Null(DataType::Real.into()),
+                "randomblob" | "zeroblob" | "unhex" => DataType::Blob.nullable(nullable),
+                _ => DataType::Null(DataType::Any.into()),
+            }
+        }
+        expr => {
+            return Err(SqlError::new("unsupported expression in the select list")
+                .fragment(expr)
+                .hint("use columns, literals, operators, case, cast and sqlite functions"))
+        }
+    };
+
+    Ok(data_type)
+}
+
 #[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
 enum DataType {
     Integer,
@@ -1086,9 +3209,54 @@ enum DataType {
     #[default]
     Blob,
     Any,
+    Bool,
+    DateTime,
+    Date,
+    Json,
+    Custom(syn::Type),
+    Enum(Ident, Vec<String>),
     Null(Box<DataType>),
 }
 
+impl DataType {
+    fn inner(&self) -> &DataType {
+        match self {
+            DataType::Null(data_type) => data_type.inner(),
+            data_type => data_type,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(self, DataType::Null(_))
+    }
+
+    fn nullable(self, nullable: bool) -> DataType {
+        match nullable || self.is_null() {
+            true => DataType::Null(self.inner().clone().into()),
+            false => self,
+        }
+    }
+
+    // the type of a value that may come from either side, like case branches
+    fn union(&self, other: &DataType) -> DataType {
+        let data_type = match (self.inner(), other.inner()) {
+            (a, b) if a == b => a.clone(),
+            (DataType::Any, data_type) | (data_type, DataType::Any) => data_type.clone(),
+            (DataType::Integer, DataType::Real) | (DataType::Real, DataType::Integer) => {
+                DataType::Real
+            }
+            (DataType::Integer, DataType::Bool) | (DataType::Bool, DataType::Integer) => {
+                DataType::Integer
+            }
+            (DataType::Text, DataType::Enum(..)) | (DataType::Enum(..), DataType::Text) => {
+                DataType::Text
+            }
+            _ => DataType::Any,
+        };
+        data_type.nullable(self.is_null() || other.is_null())
+    }
+}
+
 fn full_column_name(table_name: Option<&String>, column_name: String) -> String {
     match table_name {
         Some(table_name) => format!("{}.{}", table_name, column_name),
@@ -1096,32 +3264,210 @@ fn full_column_name(table_name: Option<&String>, column_name: String) -> String
     }
 }
 
-fn column(table_name: Option<&String>, value: &sqlparser::ast::ColumnDef) -> Column {
+fn column(
+    table_name: Option<&String>,
+    value: &sqlparser::ast::ColumnDef,
+    types: &[(Ident, syn::Type)],
+) -> Column {
     let name = value.name.to_string();
     let full_name = full_column_name(table_name, name.clone());
-    let inner_data_type = match &value.data_type {
-        sqlparser::ast::DataType::Blob(_) => DataType::Blob,
-        sqlparser::ast::DataType::Integer(_) => DataType::Integer,
-        sqlparser::ast::DataType::Int(_) => DataType::Integer,
-        sqlparser::ast::DataType::Real => DataType::Real,
-        sqlparser::ast::DataType::Text => DataType::Text,
-        _ => DataType::Any,
+    let inner_data_type = match types.iter().find(|(column, _)| *column == name) {
+        Some((_, ty)) => DataType::Custom(ty.clone()),
+        None => cast_type(&value.data_type),
     };
     let data_type = match not_null(&inner_data_type, &value.options) {
         true => inner_data_type,
         false => DataType::Null(inner_data_type.into()),
     };
     let table_name = table_name.unwrap_or(&"".into()).clone();
+    let default = value.options.iter().find_map(|opt| match &opt.option {
+        sqlparser::ast::ColumnOption::Default(expr) => Some(expr.clone()),
+        _ => None,
+    });
+    let primary_key = value.options.iter().any(|opt| {
+        matches!(
+            opt.option,
+            sqlparser::ast::ColumnOption::Unique {
+                is_primary: true,
+                ..
+            }
+        )
+    });
+    let references = value.options.iter().find_map(|opt| match &opt.option {
+        sqlparser::ast::ColumnOption::ForeignKey {
+            foreign_table,
+            referred_columns,
+            ..
+        } => Some((
+            foreign_table.to_string(),
+            referred_columns.first().map(|c| c.value.clone()),
+        )),
+        _ => None,
+    });
 
     Column {
         name,
         full_name,
         table_name,
         data_type,
+        default,
+        primary_key,
+        references,
         ..Default::default()
     }
 }
 
+fn cast_type(data_type: &sqlparser::ast::DataType) -> DataType {
+    match data_type {
+        sqlparser::ast::DataType::Blob(_) => DataType::Blob,
+        sqlparser::ast::DataType::Integer(_) => DataType::Integer,
+        sqlparser::ast::DataType::Int(_) => DataType::Integer,
+        sqlparser::ast::DataType::BigInt(_) => DataType::Integer,
+        sqlparser::ast::DataType::Real => DataType::Real,
+        sqlparser::ast::DataType::Double => DataType::Real,
+        sqlparser::ast::DataType::Float(_) => DataType::Real,
+        sqlparser::ast::DataType::Text => DataType::Text,
+        sqlparser::ast::DataType::Varchar(_) => DataType::Text,
+        sqlparser::ast::DataType::Uuid => DataType::Text,
+        sqlparser::ast::DataType::Bool => DataType::Bool,
+        sqlparser::ast::DataType::Boolean => DataType::Bool,
+        sqlparser::ast::DataType::Datetime(_) => DataType::DateTime,
+        sqlparser::ast::DataType::Timestamp(..) => DataType::DateTime,
+        sqlparser::ast::DataType::Date => DataType::Date,
+        sqlparser::ast::DataType::JSON => DataType::Json,
+        _ => DataType::Any,
+    }
+}
+
+// text columns with `check (column in ('a', 'b'))` become rust enums
+fn enum_column(
+    column: Column,
+    value: &sqlparser::ast::ColumnDef,
+    table_checks: &[&sqlparser::ast::Expr],
+    struct_ident: &Ident,
+) -> Column {
+    if column.data_type.inner() != &DataType::Text {
+        return column;
+    }
+    let column_checks = value.options.iter().filter_map(|opt| match &opt.option {
+        sqlparser::ast::ColumnOption::Check(expr) => Some(expr),
+        _ => None,
+    });
+    let values = column_checks
+        .chain(table_checks.iter().copied())
+        .find_map(|expr| match expr {
+            sqlparser::ast::Expr::InList {
+                expr,
+                list,
+                negated: false,
+            } if matches!(&**expr, sqlparser::ast::Expr::Identifier(ident) if ident.value == column.name) => {
+                list.iter()
+                    .map(|value| match value {
+                        sqlparser::ast::Expr::Value(
+                            sqlparser::ast::Value::SingleQuotedString(value),
+                        ) => Some(value.clone()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+            }
+            _ => None,
+        });
+    let values = match values {
+        Some(values) => values,
+        None => return column,
+    };
+    let variants = values
+        .iter()
+        .filter_map(|v| variant_ident(v))
+        .collect::<Vec<_>>();
+    let unique = variants
+        .iter()
+        .enumerate()
+        .all(|(i, variant)| !variants[..i].contains(variant));
+    if variants.len() != values.len() || !unique {
+        return column;
+    }
+    let ident = Ident::new(
+        &format!("{}{}", struct_ident, snake_to_pascal(column.name.clone())),
+        Span::call_site(),
+    );
+    let data_type = DataType::Enum(ident, values).nullable(column.data_type.is_null());
+
+    Column {
+        data_type,
+        ..column
+    }
+}
+
+fn variant_ident(value: &str) -> Option<Ident> {
+    let words = value
+        .split(|c: char| !c.is_alphanumeric())
+        .collect::<Vec<_>>()
+        .join("_");
+    syn::parse_str::<Ident>(&snake_to_pascal(words)).ok()
+}
+
+fn enum_tokens(ident: &Ident, values: &[String]) -> TokenStream {
+    let variants: Vec<Ident> = values.iter().filter_map(|v| variant_ident(v)).collect();
+    let default = variants.first();
+
+    quote! {
+        #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+        #[serde(crate = "crate::serde")]
+        pub enum #ident {
+            #(#[serde(rename = #values)] #variants,)*
+        }
+
+        impl #ident {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    #(Self::#variants => #values,)*
+                }
+            }
+        }
+
+        impl Default for #ident {
+            fn default() -> Self {
+                Self::#default
+            }
+        }
+
+        impl std::fmt::Display for #ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for #ident {
+            type Err = String;
+
+            fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+                match s {
+                    #(#values => Ok(Self::#variants),)*
+                    s => Err(format!("invalid {} {}", stringify!(#ident), s)),
+                }
+            }
+        }
+
+        impl rusqlite::ToSql for #ident {
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                Ok(self.as_str().into())
+            }
+        }
+
+        impl rusqlite::types::FromSql for #ident {
+            fn column_result(
+                value: rusqlite::types::ValueRef<'_>,
+            ) -> rusqlite::types::FromSqlResult<Self> {
+                value
+                    .as_str()?
+                    .parse()
+                    .map_err(|err: String| rusqlite::types::FromSqlError::Other(err.into()))
+            }
+        }
+    }
+}
+
 fn not_null(data_type: &DataType, value: &Vec<sqlparser::ast::ColumnOptionDef>) -> bool {
     value.iter().any(|opt| match opt.option {
         sqlparser::ast::ColumnOption::NotNull => true,
@@ -1139,6 +3485,12 @@ fn data_type_tokens(data_type: &DataType) -> TokenStream {
         DataType::Text => quote!(String),
         DataType::Blob => quote!(Vec<u8>),
         DataType::Any => quote!(Vec<u8>),
+        DataType::Bool => quote!(bool),
+        DataType::DateTime => quote!(ryde::db::time::OffsetDateTime),
+        DataType::Date => quote!(ryde::db::time::Date),
+        DataType::Json => quote!(ryde::db::serde_json::Value),
+        DataType::Custom(ty) => quote!(#ty),
+        DataType::Enum(ident, _) => quote!(#ident),
         DataType::Null(null) => {
             let tokens = data_type_tokens(null);
             quote!(Option<#tokens>)
@@ -1146,6 +3498,64 @@ fn data_type_tokens(data_type: &DataType) -> TokenStream {
     }
 }
 
+fn default_tokens(column: &Column) -> TokenStream {
+    let name = syn::Ident::new(&column.name, proc_macro2::Span::call_site());
+    let schema_default = column
+        .default
+        .as_ref()
+        .and_then(|expr| default_value_tokens(column.data_type.inner(), expr));
+    let value = match (schema_default, &column.data_type) {
+        (Some(value), DataType::Null(_)) => quote!(Some(#value)),
+        (Some(value), _) => value,
+        (None, DataType::DateTime) => quote!(ryde::db::time::OffsetDateTime::UNIX_EPOCH),
+        (None, DataType::Date) => quote!(ryde::db::time::OffsetDateTime::UNIX_EPOCH.date()),
+        (None, _) => quote!(Default::default()),
+    };
+
+    quote!(#name: #value)
+}
+
+// rust value of a column's `default` clause, none when it can't be known at compile time
+fn default_value_tokens(data_type: &DataType, expr: &sqlparser::ast::Expr) -> Option<TokenStream> {
+    use sqlparser::ast::{Expr, Value};
+    let text = match expr {
+        Expr::Nested(expr) => return default_value_tokens(data_type, expr),
+        Expr::Value(Value::SingleQuotedString(text)) => Some(text),
+        _ => None,
+    };
+    let literal = expr.to_string().to_lowercase();
+    match (data_type, text) {
+        (DataType::Integer, None) => {
+            let value = proc_macro2::Literal::i64_unsuffixed(literal.parse().ok()?);
+            Some(quote!(#value))
+        }
+        (DataType::Real, None) => {
+            let value = proc_macro2::Literal::f64_unsuffixed(literal.parse().ok()?);
+            Some(quote!(#value))
+        }
+        (DataType::Bool, None) => match literal.as_str() {
+            "1" | "true" => Some(quote!(true)),
+            "0" | "false" => Some(quote!(false)),
+            _ => None,
+        },
+        (DataType::Text, Some(text)) => Some(quote!(#text.into())),
+        (DataType::Enum(ident, _), Some(text)) => {
+            let variant = variant_ident(text)?;
+            Some(quote!(#ident::#variant))
+        }
+        (DataType::Json, Some(text)) => {
+            Some(quote!(ryde::db::serde_json::from_str(#text).unwrap_or_default()))
+        }
+        (DataType::DateTime, None) if literal == "current_timestamp" => {
+            Some(quote!(ryde::db::time::OffsetDateTime::now_utc()))
+        }
+        (DataType::Date, None) if literal == "current_date" => {
+            Some(quote!(ryde::db::time::OffsetDateTime::now_utc().date()))
+        }
+        _ => None,
+    }
+}
+
 fn column_tokens(column: &Column) -> TokenStream {
     let data_type = data_type_tokens(&column.data_type);
     let name = syn::Ident::new(&column.name, proc_macro2::Span::call_site());
@@ -1171,9 +3581,46 @@ fn param_tokens(column: &Column) -> TokenStream {
     quote!(#name)
 }
 
+// rows are borrowed so async fns own a copy before moving it to the connection thread
+fn owned_arg_tokens(column: &Column) -> Option<TokenStream> {
+    let name = syn::Ident::new(&column.name, proc_macro2::Span::call_site());
+    column
+        .arg
+        .as_ref()
+        .map(|_| quote!(let #name = #name.to_owned();))
+}
+
+fn forward_param_tokens(column: &Column) -> TokenStream {
+    let name = syn::Ident::new(&column.name, proc_macro2::Span::call_site());
+    match column.arg {
+        Some(_) => quote!(&#name),
+        None => quote!(#name),
+    }
+}
+
+// the sql param of a row argument is a field, lists are json arrays for json_each
+fn arg_param_tokens(column: &Column) -> Option<TokenStream> {
+    let name = syn::Ident::new(&column.name, proc_macro2::Span::call_site());
+    if column.list {
+        return Some(quote!(let #name = ryde::db::list_param(&#name)?;));
+    }
+    match column.arg.as_ref()? {
+        Arg::Row(_, field) => {
+            let field = syn::Ident::new(field, proc_macro2::Span::call_site());
+            Some(quote!(let #name = &#name.#field;))
+        }
+        Arg::Rows(_, field) => {
+            let field = syn::Ident::new(field, proc_macro2::Span::call_site());
+            Some(quote! {
+                let #name = ryde::db::list_param(#name.iter().map(|row| &row.#field))?;
+            })
+        }
+    }
+}
+
 fn row_tokens(column: &Column) -> TokenStream {
-    let lit_str = &column.name;
-    let ident = syn::Ident::new(&lit_str, proc_macro2::Span::call_site());
+    let ident = syn::Ident::new(&column.name, proc_macro2::Span::call_site());
+    let lit_str = column.label.as_ref().unwrap_or(&column.name);
 
     quote!(#ident: row.get(#lit_str)?)
 }
@@ -1181,22 +3628,20 @@ fn row_tokens(column: &Column) -> TokenStream {
 fn fn_tokens(column: &Column) -> TokenStream {
     let lit_str = &column.name;
     let ident = syn::Ident::new(&lit_str, proc_macro2::Span::call_site());
-    let fn_type = fn_type(&column.data_type);
+    let fn_type = arg_type_tokens(column);
 
     quote!(#ident: #fn_type)
 }
 
-fn fn_type(data_type: &DataType) -> TokenStream {
-    match data_type {
-        DataType::Integer => quote!(i64),
-        DataType::Real => quote!(f64),
-        DataType::Text => quote!(String),
-        DataType::Blob => quote!(Vec<u8>),
-        DataType::Any => quote!(Vec<u8>),
-        DataType::Null(dt) => {
-            let dt = fn_type(&*dt);
-            quote!(Option<#dt>)
+fn arg_type_tokens(column: &Column) -> TokenStream {
+    match &column.arg {
+        Some(Arg::Row(struct_ident, _)) => quote!(&#struct_ident),
+        Some(Arg::Rows(struct_ident, _)) => quote!(&[#struct_ident]),
+        None if column.list => {
+            let data_type = data_type_tokens(column.data_type.inner());
+            quote!(Vec<#data_type>)
         }
+        None => data_type_tokens(&column.data_type),
     }
 }
 
@@ -1204,14 +3649,23 @@ fn fn_type(data_type: &DataType) -> TokenStream {
 pub struct SqlExpr {
     ident: Ident,
     sql: String,
+    span: Span,
     statements: Vec<Statement>,
     cast: Cast,
+    migration: bool,
+    types: Vec<(Ident, syn::Type)>,
+    crud: bool,
+    triggers: bool,
+    functions: Vec<Ident>,
+    // the row argument that replaces the params of relation helpers
+    relation: Option<Column>,
+    plural: Option<String>,
 }
 
 #[derive(Debug)]
 struct Input {
     defs: Vec<SqlExpr>,
-    columns: HashSet<Column>,
+    columns: Vec<Column>,
 }
 
 #[derive(Debug)]
@@ -1242,6 +3696,8 @@ enum Stmt {
         in_cols: Vec<Column>,
         out_cols: Vec<Column>,
         ret: QueryReturn,
+        // selects run on a reader connection
+        read: bool,
     },
     CreateTable {
         sql: String,
@@ -1249,6 +3705,18 @@ enum Stmt {
         cast: Cast,
         cols: Vec<Column>,
     },
+    InsertMany {
+        ident: Ident,
+        sql: String,
+        in_cols: Vec<Column>,
+        rows: Vec<Column>,
+        returning: Option<Ident>,
+    },
+    Migrate {
+        ident: Ident,
+        sqls: Vec<String>,
+        tables: Vec<(Ident, Vec<Column>)>,
+    },
 }
 
 #[derive(Default, Debug, Clone, PartialEq)]
@@ -1267,79 +3735,207 @@ impl syn::parse::Parse for SqlExprs {
         let mut sql_exprs: Vec<SqlExpr> = Vec::new();
         while !input.is_empty() {
             let stmt: syn::Stmt = input.parse()?;
-            let sql_expr = sql_expr(stmt);
-            sql_exprs.push(sql_expr);
+            sql_exprs.extend(sql_expr(stmt)?);
         }
         Ok(SqlExprs(sql_exprs))
     }
 }
 
-fn cast(ty: Box<syn::Type>) -> Cast {
-    match &*ty {
-        syn::Type::Path(syn::TypePath { path, .. }) => {
-            let seg = path
-                .segments
-                .first()
-                .expect("Only Vec<T> or T are supported");
-            match seg.arguments {
-                syn::PathArguments::None => Cast::T(
-                    path.get_ident()
-                        .cloned()
-                        .expect("Only Vec<T> or T are supported"),
-                ),
-                syn::PathArguments::AngleBracketed(ref args) => match args.args.first() {
-                    Some(syn::GenericArgument::Type(ty)) => match ty {
-                        syn::Type::Path(syn::TypePath { path, .. }) => Cast::Vec(
-                            path.get_ident()
-                                .cloned()
-                                .expect("Only Vec<T> or T are supported"),
-                        ),
-                        _ => panic!("Only Vec<T> or T are support"),
-                    },
-                    _ => panic!("Only Vec<T> or T are support"),
-                },
-                syn::PathArguments::Parenthesized(_) => todo!(),
+fn cast(ty: Box<syn::Type>) -> Result<Cast> {
+    let error = || syn::Error::new_spanned(&ty, "only `T` or `Vec<T>` casts are supported");
+    let path = match &*ty {
+        syn::Type::Path(syn::TypePath { path, .. }) => path,
+        _ => return Err(error()),
+    };
+    let seg = path.segments.last().ok_or_else(error)?;
+    match &seg.arguments {
+        syn::PathArguments::None => Ok(Cast::T(path.get_ident().cloned().ok_or_else(error)?)),
+        syn::PathArguments::AngleBracketed(args) if seg.ident == "Vec" => match args.args.first() {
+            Some(syn::GenericArgument::Type(syn::Type::Path(syn::TypePath { path, .. }))) => {
+                Ok(Cast::Vec(path.get_ident().cloned().ok_or_else(error)?))
             }
-        }
-        _ => panic!("Only Vec<T> or T are supported"),
+            _ => Err(error()),
+        },
+        _ => Err(error()),
     }
 }
 
-fn sql_expr_parts(expr: Box<syn::Expr>) -> (String, Cast) {
+fn sql_expr_parts(expr: Box<syn::Expr>) -> Result<(String, Span, Cast, Vec<syn::Attribute>)> {
     match *expr {
-        syn::Expr::Cast(ExprCast { expr, ty, .. }) => {
-            let (sql, _) = sql_expr_parts(expr);
-            let cast = cast(ty);
-            (sql, cast)
-        }
-        syn::Expr::Lit(syn::ExprLit { lit, .. }) => match lit {
-            syn::Lit::Str(lit_str) => (lit_str.value(), Cast::None),
-            _ => panic!("Expected string literal for sql"),
-        },
-        _ => panic!("Expected string literal for sql"),
+        syn::Expr::Cast(ExprCast {
+            attrs, expr, ty, ..
+        }) => {
+            let (sql, span, _, mut lit_attrs) = sql_expr_parts(expr)?;
+            let cast = cast(ty)?;
+            lit_attrs.extend(attrs);
+            Ok((sql, span, cast, lit_attrs))
+        }
+        syn::Expr::Lit(syn::ExprLit {
+            attrs,
+            lit: syn::Lit::Str(lit_str),
+        }) => Ok((lit_str.value(), lit_str.span(), Cast::None, attrs)),
+        expr => Err(syn::Error::new_spanned(
+            expr,
+            "expected a string literal of sql like r#\"select ...\"#",
+        )),
     }
 }
 
-fn sql_expr(stmt: syn::Stmt) -> SqlExpr {
-    match stmt {
-        syn::Stmt::Local(syn::Local { pat, init, .. }) => {
-            let ident = match pat {
-                syn::Pat::Ident(PatIdent { ident, .. }) => ident,
-                _ => todo!(),
-            };
-            let (sql, cast) = match init {
-                Some(LocalInit { expr, .. }) => sql_expr_parts(expr),
-                None => todo!(),
-            };
-            SqlExpr {
+fn sql_expr(stmt: syn::Stmt) -> Result<Vec<SqlExpr>> {
+    let (attrs, pat, init) = match stmt {
+        syn::Stmt::Local(syn::Local {
+            attrs, pat, init, ..
+        }) => (attrs, pat, init),
+        stmt => {
+            return Err(syn::Error::new_spanned(
+                stmt,
+                "expected `let name = r#\"sql\"#;` statements",
+            ))
+        }
+    };
+    let ident = match pat {
+        syn::Pat::Ident(PatIdent { ident, .. }) => ident,
+        pat => {
+            return Err(syn::Error::new_spanned(
+                pat,
+                "expected a name like `let posts`",
+            ))
+        }
+    };
+    let expr = match init {
+        Some(LocalInit { expr, .. }) => expr,
+        None => {
+            return Err(syn::Error::new_spanned(
                 ident,
+                "expected sql like `let posts = r#\"select ...\"#;`",
+            ))
+        }
+    };
+    // an array of sql strings is an ordered list of migrations
+    let (exprs, migration) = match *expr {
+        syn::Expr::Array(ExprArray { elems, .. }) => {
+            (elems.into_iter().map(Box::new).collect::<Vec<_>>(), true)
+        }
+        expr => (vec![Box::new(expr)], false),
+    };
+    exprs
+        .into_iter()
+        .map(|expr| {
+            let (sql, span, cast, expr_attrs) = sql_expr_parts(expr)?;
+            let mut types = vec![];
+            let mut crud = false;
+            let mut triggers = false;
+            let mut functions = vec![];
+            let mut plural = None;
+            for attr in attrs.iter().chain(&expr_attrs) {
+                if attr.path().is_ident("types") {
+                    types.extend(types_attr(attr)?);
+                } else if attr.path().is_ident("crud") {
+                    crud = true;
+                    if let syn::Meta::List(_) = attr.meta {
+                        attr.parse_nested_meta(|meta| match meta.path.is_ident("plural") {
+                            true => {
+                                plural = Some(meta.value()?.parse::<LitStr>()?.value());
+                                Ok(())
+                            }
+                            false => Err(meta.error("expected #[crud(plural = \"name\")]")),
+                        })?;
+                    } else {
+                        attr.meta.require_path_only()?;
+                    }
+                } else if attr.path().is_ident("triggers") {
+                    attr.meta.require_path_only()?;
+                    triggers = true;
+                } else if attr.path().is_ident("functions") {
+                    functions.extend(attr.parse_args_with(
+                        syn::punctuated::Punctuated::<Ident, syn::Token![,]>::parse_terminated,
+                    )?);
+                } else {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        "unsupported attribute, expected #[types(column = Type)], #[crud], #[crud(plural = \"name\")], #[triggers] or #[functions(name)]",
+                    ));
+                }
+            }
+            Ok(SqlExpr {
+                ident: ident.clone(),
                 sql,
+                span,
                 statements: vec![],
                 cast,
-            }
+                migration,
+                types,
+                crud,
+                triggers,
+                functions,
+                relation: None,
+                plural,
+            })
+        })
+        .collect()
+}
+
+// #[types(column = RustType, ...)] maps columns to types that implement ToSql and FromSql
+fn types_attr(attr: &syn::Attribute) -> Result<Vec<(Ident, syn::Type)>> {
+    let mappings = attr.parse_args_with(
+        syn::punctuated::Punctuated::<TypeMapping, syn::Token![,]>::parse_terminated,
+    )?;
+
+    Ok(mappings
+        .into_iter()
+        .map(|TypeMapping(column, ty)| (column, ty))
+        .collect())
+}
+
+struct TypeMapping(Ident, syn::Type);
+
+impl syn::parse::Parse for TypeMapping {
+    fn parse(input: syn::parse::ParseStream) -> Result<Self> {
+        let column = input.parse()?;
+        input.parse::<syn::Token![=]>()?;
+        let ty = input.parse()?;
+
+        Ok(TypeMapping(column, ty))
+    }
+}
+
+type SqlResult<T> = std::result::Result<T, SqlError>;
+
+#[derive(Debug)]
+struct SqlError {
+    message: String,
+    fragment: Option<String>,
+    hint: Option<&'static str>,
+}
+
+impl SqlError {
+    fn new(message: impl std::fmt::Display) -> Self {
+        Self {
+            message: message.to_string(),
+            fragment: None,
+            hint: None,
+        }
+    }
+
+    fn fragment(mut self, fragment: impl std::fmt::Display) -> Self {
+        self.fragment = Some(fragment.to_string());
+        self
+    }
+
+    fn hint(mut self, hint: &'static str) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    fn into_syn_error(self, ident: &Ident, span: Span) -> syn::Error {
+        let mut message = format!("{}: {}", ident, self.message);
+        if let Some(fragment) = self.fragment {
+            message.push_str(&format!(" in `{}`", fragment));
         }
-        syn::Stmt::Item(_) => todo!(),
-        syn::Stmt::Expr(_, _) => todo!(),
-        syn::Stmt::Macro(_) => todo!(),
+        if let Some(hint) = self.hint {
+            message.push_str(&format!("\nhint: {}", hint));
+        }
+
+        syn::Error::new(span, message)
     }
 }
@@ -1,8 +1,630 @@
 pub use rusqlite;
+use rusqlite::backup::{Backup, Progress, StepResult};
+use rusqlite::types::{ToSqlOutput, Value};
+use rusqlite::{functions, hooks, OpenFlags, ToSql};
 pub use ryde_macros::db;
+pub use serde_json;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+pub use time;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 pub use tokio_rusqlite::{self, Connection};
 extern crate self as ryde_db;
 
+type ConnectionHook = Arc<dyn Fn(&rusqlite::Connection) -> rusqlite::Result<()> + Send + Sync>;
+
+// settings for the connections opened by `db_with`
+#[derive(Clone)]
+pub struct DbOptions {
+    statement_cache_capacity: usize,
+    readers: usize,
+    read_only: bool,
+    foreign_keys: bool,
+    journal_mode: String,
+    synchronous: String,
+    busy_timeout: Option<Duration>,
+    cache_size: Option<i64>,
+    mmap_size: Option<u64>,
+    temp_store: Option<String>,
+    init_sql: Vec<String>,
+    reader_init_sql: Vec<String>,
+    functions: Vec<ConnectionHook>,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            statement_cache_capacity: 128,
+            readers: 4,
+            read_only: false,
+            foreign_keys: true,
+            journal_mode: "WAL".into(),
+            synchronous: "NORMAL".into(),
+            busy_timeout: Some(Duration::from_secs(5)),
+            cache_size: None,
+            mmap_size: None,
+            temp_store: None,
+            init_sql: vec![],
+            reader_init_sql: vec![],
+            functions: vec![],
+        }
+    }
+}
+
+impl DbOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // generated queries are prepared once and kept in a lru cache of this size
+    pub fn statement_cache_capacity(mut self, capacity: usize) -> Self {
+        self.statement_cache_capacity = capacity;
+        self
+    }
+
+    // read only connections that selects are spread over, in memory databases have none
+    pub fn readers(mut self, readers: usize) -> Self {
+        self.readers = readers;
+        self
+    }
+
+    // opens the writer read only too and leaves the journal mode alone
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn foreign_keys(mut self, foreign_keys: bool) -> Self {
+        self.foreign_keys = foreign_keys;
+        self
+    }
+
+    pub fn journal_mode(mut self, journal_mode: impl Into<String>) -> Self {
+        self.journal_mode = journal_mode.into();
+        self
+    }
+
+    pub fn synchronous(mut self, synchronous: impl Into<String>) -> Self {
+        self.synchronous = synchronous.into();
+        self
+    }
+
+    // how long to wait on a locked database before returning SQLITE_BUSY
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = Some(timeout);
+        self
+    }
+
+    // pages when positive, kibibytes when negative
+    pub fn cache_size(mut self, cache_size: i64) -> Self {
+        self.cache_size = Some(cache_size);
+        self
+    }
+
+    pub fn mmap_size(mut self, bytes: u64) -> Self {
+        self.mmap_size = Some(bytes);
+        self
+    }
+
+    // default, file or memory
+    pub fn temp_store(mut self, temp_store: impl Into<String>) -> Self {
+        self.temp_store = Some(temp_store.into());
+        self
+    }
+
+    // runs on the writer after the pragmas, also when the database is opened read only
+    pub fn init_sql(mut self, sql: impl Into<String>) -> Self {
+        self.init_sql.push(sql.into());
+        self
+    }
+
+    // runs on each reader after the pragmas, readers are opened read only
+    pub fn reader_init_sql(mut self, sql: impl Into<String>) -> Self {
+        self.reader_init_sql.push(sql.into());
+        self
+    }
+
+    // registers a scalar function on every connection, n_args of -1 takes any number of args
+    pub fn function<F, T>(mut self, name: impl Into<String>, n_args: i32, f: F) -> Self
+    where
+        F: Fn(&functions::Context<'_>) -> rusqlite::Result<T> + Send + Sync + 'static,
+        T: ToSql,
+    {
+        let name = name.into();
+        let f = Arc::new(f);
+        self.functions.push(Arc::new(move |conn| {
+            let f = f.clone();
+            conn.create_scalar_function(
+                name.as_str(),
+                n_args,
+                functions::FunctionFlags::SQLITE_UTF8,
+                move |ctx| f(ctx),
+            )
+        }));
+        self
+    }
+
+    // opens the writer and the readers for `db_with`
+    #[doc(hidden)]
+    pub async fn open(&self, path: &Path) -> crate::Result<(Connection, Vec<Connection>, Changes)> {
+        let flags = match self.read_only {
+            true => OpenFlags::SQLITE_OPEN_READ_ONLY,
+            false => OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        };
+        let writer = self.connection(path, flags, true).await?;
+        let changes = Changes::default();
+        let hooks = changes.clone();
+        writer
+            .call(move |conn| {
+                hooks.install(conn);
+                Ok(())
+            })
+            .await?;
+        // every in memory connection is its own database so those only get the writer
+        let memory = path.as_os_str().is_empty()
+            || path == Path::new(":memory:")
+            || path.to_string_lossy().contains("mode=memory");
+        let mut readers = vec![];
+        for _ in 0..if memory { 0 } else { self.readers } {
+            readers.push(
+                self.connection(path, OpenFlags::SQLITE_OPEN_READ_ONLY, false)
+                    .await?,
+            );
+        }
+
+        Ok((writer, readers, changes))
+    }
+
+    async fn connection(
+        &self,
+        path: &Path,
+        flags: OpenFlags,
+        primary: bool,
+    ) -> crate::Result<Connection> {
+        let connection = Connection::open_with_flags(
+            path,
+            flags | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .await?;
+        let options = self.clone();
+        connection
+            .call(move |conn| Ok(options.configure(conn, primary)))
+            .await??;
+
+        Ok(connection)
+    }
+
+    // the primary connection is the writer, or the only read only connection
+    // that isn't a pool reader when the database is opened read only
+    fn configure(&self, conn: &rusqlite::Connection, primary: bool) -> rusqlite::Result<()> {
+        conn.set_prepared_statement_cache_capacity(self.statement_cache_capacity);
+        if let Some(timeout) = self.busy_timeout {
+            conn.busy_timeout(timeout)?;
+        }
+        let mut pragmas = vec![
+            format!("PRAGMA foreign_keys = {}", self.foreign_keys),
+            format!("PRAGMA synchronous = {}", self.synchronous),
+        ];
+        // the journal mode is stored in the database file so only the writer sets it
+        if primary && !self.read_only {
+            pragmas.push(format!("PRAGMA journal_mode = {}", self.journal_mode));
+        }
+        if let Some(cache_size) = self.cache_size {
+            pragmas.push(format!("PRAGMA cache_size = {}", cache_size));
+        }
+        if let Some(mmap_size) = self.mmap_size {
+            pragmas.push(format!("PRAGMA mmap_size = {}", mmap_size));
+        }
+        if let Some(temp_store) = &self.temp_store {
+            pragmas.push(format!("PRAGMA temp_store = {}", temp_store));
+        }
+        conn.execute_batch(&pragmas.join(";\n"))?;
+        for function in &self.functions {
+            function(conn)?;
+        }
+        let init_sql = match primary {
+            true => &self.init_sql,
+            false => &self.reader_init_sql,
+        };
+        for sql in init_sql {
+            conn.execute_batch(sql)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub action: Action,
+    pub table: String,
+    pub rowid: i64,
+}
+
+thread_local! {
+    // rows changed by the open transaction on this connection's thread
+    static PENDING_CHANGES: RefCell<Vec<Change>> = const { RefCell::new(vec![]) };
+}
+
+// per table broadcast channels fed by the writer's hooks, the commit hook runs before
+// the commit is visible to other connections so committed changes wait until `flush`
+#[derive(Clone, Default)]
+pub struct Changes {
+    channels: Arc<Mutex<HashMap<String, broadcast::Sender<Change>>>>,
+    committed: Arc<Mutex<Vec<Change>>>,
+}
+
+impl Changes {
+    pub fn subscribe(&self, table: &str) -> broadcast::Receiver<Change> {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(table.to_string())
+            .or_insert_with(|| broadcast::channel(1024).0)
+            .subscribe()
+    }
+
+    fn install(&self, conn: &rusqlite::Connection) {
+        conn.update_hook(Some(
+            |action: hooks::Action, _: &str, table: &str, rowid: i64| {
+                let action = match action {
+                    hooks::Action::SQLITE_INSERT => Action::Insert,
+                    hooks::Action::SQLITE_UPDATE => Action::Update,
+                    hooks::Action::SQLITE_DELETE => Action::Delete,
+                    _ => return,
+                };
+                PENDING_CHANGES.with_borrow_mut(|pending| {
+                    pending.push(Change {
+                        action,
+                        table: table.to_string(),
+                        rowid,
+                    })
+                });
+            },
+        ));
+        let committed = self.committed.clone();
+        conn.commit_hook(Some(move || {
+            committed.lock().unwrap().extend(PENDING_CHANGES.take());
+            false
+        }));
+        conn.rollback_hook(Some(|| PENDING_CHANGES.with_borrow_mut(Vec::clear)));
+    }
+
+    // called after each call on the writer returns, by then its commits are done
+    #[doc(hidden)]
+    pub fn flush(&self) {
+        let changes = std::mem::take(&mut *self.committed.lock().unwrap());
+        let channels = self.channels.lock().unwrap();
+        for change in changes {
+            if let Some(sender) = channels.get(&change.table) {
+                let _ = sender.send(change);
+            }
+        }
+    }
+}
+
+// `rollback to` a savepoint doesn't call the rollback hook so Tx::transaction marks
+// where its savepoint started and drops the changes after it itself
+#[doc(hidden)]
+pub fn pending_changes() -> usize {
+    PENDING_CHANGES.with_borrow(Vec::len)
+}
+
+#[doc(hidden)]
+pub fn discard_changes(from: usize) {
+    PENDING_CHANGES.with_borrow_mut(|pending| pending.truncate(from));
+}
+
+// `in (?)` binds a json array for json_each, each value goes through ToSql first so the
+// array holds what binding it on its own would store, like the text of a datetime
+#[doc(hidden)]
+pub fn list_param<T: ToSql>(
+    values: impl IntoIterator<Item = T>,
+) -> rusqlite::Result<serde_json::Value> {
+    values
+        .into_iter()
+        .map(|value| {
+            let value = match value.to_sql()? {
+                ToSqlOutput::Borrowed(value) => value.into(),
+                ToSqlOutput::Owned(value) => value,
+                _ => Value::Blob(vec![]),
+            };
+            match value {
+                Value::Null => Ok(serde_json::Value::Null),
+                Value::Integer(value) => Ok(value.into()),
+                Value::Real(value) => Ok(value.into()),
+                Value::Text(value) => Ok(value.into()),
+                Value::Blob(_) => Err(rusqlite::Error::ToSqlConversionFailure(
+                    "blobs can't be bound as a list".into(),
+                )),
+            }
+        })
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map(serde_json::Value::Array)
+}
+
+// copies the database page by page with sqlite's online backup api, the connection
+// is held for the whole copy so no writes can make it start over
+pub async fn backup(
+    connection: &Connection,
+    path: impl AsRef<Path>,
+    mut progress: impl FnMut(Progress) + Send + 'static,
+) -> crate::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    connection
+        .call(move |conn| Ok(backup_connection(conn, &path, &mut progress)?))
+        .await?;
+
+    Ok(())
+}
+
+fn backup_connection(
+    conn: &rusqlite::Connection,
+    path: &Path,
+    progress: &mut impl FnMut(Progress),
+) -> rusqlite::Result<()> {
+    let mut dst = rusqlite::Connection::open(path)?;
+    let backup = Backup::new(conn, &mut dst)?;
+    loop {
+        let step = backup.step(1024)?;
+        progress(backup.progress());
+        match step {
+            StepResult::Done => return Ok(()),
+            StepResult::More => {}
+            _ => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+// writes a compacted copy of the database, the file must not exist yet
+pub async fn snapshot(connection: &Connection, path: impl AsRef<Path>) -> crate::Result<()> {
+    let path = path.as_ref().to_string_lossy().to_string();
+    connection
+        .call(move |conn| {
+            conn.execute("vacuum into ?", [path])?;
+            Ok(())
+        })
+        .await?;
+
+    Ok(())
+}
+
+// backs up to <dir>/backup-<utc timestamp>.db every interval and only keeps the newest files,
+// the task stops at the first failed backup and its handle returns the error
+pub fn backup_every(
+    connection: Connection,
+    dir: impl AsRef<Path>,
+    interval: Duration,
+    keep: usize,
+) -> JoinHandle<crate::Result<()>> {
+    let dir = dir.as_ref().to_path_buf();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            backup_rotate(&connection, &dir, keep).await?;
+        }
+    })
+}
+
+// one step of backup_every, the copy is renamed into place when it's complete
+pub async fn backup_rotate(connection: &Connection, dir: &Path, keep: usize) -> crate::Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(format!("backup-{}.db", timestamp(None)?));
+    let partial = path.with_extension("db.partial");
+    backup(connection, &partial, |_| {}).await?;
+    tokio::fs::rename(&partial, &path).await?;
+    let mut backups = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("backup-") && name.ends_with(".db") {
+            backups.push(entry.path());
+        }
+    }
+    // timestamps sort in the order they were taken
+    backups.sort();
+    for path in &backups[..backups.len().saturating_sub(keep)] {
+        tokio::fs::remove_file(path).await?;
+    }
+
+    Ok(())
+}
+
+// utc timestamps that sort the same as strings and as times
+fn timestamp(at: Option<time::OffsetDateTime>) -> crate::Result<String> {
+    at.unwrap_or_else(time::OffsetDateTime::now_utc)
+        .to_offset(time::UtcOffset::UTC)
+        .format(time::macros::format_description!(
+            "[year][month][day]T[hour][minute][second][subsecond digits:6]Z"
+        ))
+        .map_err(|err| crate::Error::Io(err.to_string()))
+}
+
+// ships the wal to <dir>/wal every interval and takes a new snapshot into <dir>/snapshots
+// every snapshot_interval, `restore` rebuilds the database from them, the task stops at
+// the first failed round and its handle returns the error, sqlite's automatic checkpoints
+// are off while it runs and come back when the task ends or is aborted
+pub fn replicate(
+    connection: Connection,
+    dir: impl AsRef<Path>,
+    interval: Duration,
+    snapshot_interval: Duration,
+) -> JoinHandle<crate::Result<()>> {
+    let dir = dir.as_ref().to_path_buf();
+    tokio::spawn(async move {
+        let _guard = AutoCheckpointOff::new(connection.clone()).await?;
+        let mut interval = tokio::time::interval(interval);
+        let mut last_snapshot: Option<std::time::Instant> = None;
+        loop {
+            interval.tick().await;
+            let snapshot = last_snapshot.is_none_or(|at| at.elapsed() >= snapshot_interval);
+            match replicate_once(&connection, &dir, snapshot).await {
+                Ok(()) if snapshot => last_snapshot = Some(std::time::Instant::now()),
+                Ok(()) => {}
+                // readers held the wal, the next tick ships it
+                Err(crate::Error::DatabaseBusy) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    })
+}
+
+// sqlite restarts the wal after its own checkpoints which would drop frames between two
+// rounds, the previous wal_autocheckpoint is put back on drop
+struct AutoCheckpointOff {
+    connection: Connection,
+    pages: i64,
+}
+
+impl AutoCheckpointOff {
+    async fn new(connection: Connection) -> crate::Result<Self> {
+        let pages = connection
+            .call(|conn| {
+                let pages =
+                    conn.pragma_query_value(None, "wal_autocheckpoint", |row| row.get(0))?;
+                conn.pragma_update(None, "wal_autocheckpoint", 0)?;
+                Ok(pages)
+            })
+            .await?;
+
+        Ok(Self { connection, pages })
+    }
+}
+
+impl Drop for AutoCheckpointOff {
+    fn drop(&mut self) {
+        let (connection, pages) = (self.connection.clone(), self.pages);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                connection
+                    .call(move |conn| Ok(conn.pragma_update(None, "wal_autocheckpoint", pages)?))
+                    .await
+            });
+        }
+    }
+}
+
+// one step of replicate, runs on the writer so no commits land between copying
+// the wal and checkpointing it, when called directly nothing may checkpoint between
+// calls so wal_autocheckpoint should be 0 like `replicate` sets it
+pub async fn replicate_once(
+    connection: &Connection,
+    dir: impl AsRef<Path>,
+    snapshot: bool,
+) -> crate::Result<()> {
+    let dir = dir.as_ref().to_path_buf();
+    connection
+        .call(move |conn| Ok(ship_wal(conn, &dir, snapshot)))
+        .await?
+}
+
+fn ship_wal(conn: &rusqlite::Connection, dir: &Path, snapshot: bool) -> crate::Result<()> {
+    let path = match conn.path() {
+        Some(path) if !path.is_empty() => path.to_string(),
+        _ => {
+            return Err(crate::Error::Database(
+                "in memory databases can't be replicated".into(),
+            ))
+        }
+    };
+    let snapshots = dir.join("snapshots");
+    let segments = dir.join("wal");
+    std::fs::create_dir_all(&snapshots)?;
+    std::fs::create_dir_all(&segments)?;
+    let wal = match std::fs::read(format!("{}-wal", path)) {
+        Ok(wal) => wal,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(err) => return Err(err.into()),
+    };
+    let segment = segments.join(format!("{}.wal", timestamp(None)?));
+    if !wal.is_empty() {
+        std::fs::write(segment.with_extension("wal.partial"), &wal)?;
+    }
+    // a reader on an older snapshot keeps the wal from being truncated, the next segment
+    // would ship these frames again so this round is skipped
+    let busy: i64 = conn.query_row("pragma wal_checkpoint(truncate)", [], |row| row.get(0))?;
+    if busy != 0 {
+        let _ = std::fs::remove_file(segment.with_extension("wal.partial"));
+        return Err(crate::Error::DatabaseBusy);
+    }
+    if !wal.is_empty() {
+        std::fs::rename(segment.with_extension("wal.partial"), &segment)?;
+    }
+    if snapshot || std::fs::read_dir(&snapshots)?.next().is_none() {
+        // the backup api keeps page numbers so later wal frames still apply
+        let snapshot = snapshots.join(format!("{}.db", timestamp(None)?));
+        backup_connection(conn, &snapshot.with_extension("db.partial"), &mut |_| {})?;
+        std::fs::rename(snapshot.with_extension("db.partial"), &snapshot)?;
+    }
+
+    Ok(())
+}
+
+// rebuilds a replicated database at path from the newest snapshot before point_in_time
+// and the wal shipped after it, None restores everything
+pub fn restore(
+    dir: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+    point_in_time: Option<time::OffsetDateTime>,
+) -> crate::Result<()> {
+    let (dir, path) = (dir.as_ref(), path.as_ref());
+    if path.exists() {
+        return Err(crate::Error::Io(format!(
+            "{} already exists",
+            path.display()
+        )));
+    }
+    let until = match point_in_time {
+        Some(at) => timestamp(Some(at))?,
+        None => "~".into(),
+    };
+    let files = |dir: PathBuf, extension: &str| -> crate::Result<Vec<(String, PathBuf)>> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(ext)) if ext == extension => {
+                    files.push((stem.to_string_lossy().to_string(), path))
+                }
+                _ => {}
+            }
+        }
+        files.sort();
+        Ok(files)
+    };
+    let Some((snapshot_at, snapshot)) = files(dir.join("snapshots"), "db")?
+        .into_iter()
+        .rfind(|(at, _)| *at <= until)
+    else {
+        return Err(crate::Error::NotFound);
+    };
+    std::fs::copy(snapshot, path)?;
+    rusqlite::Connection::open(path)?.pragma_update(None, "journal_mode", "wal")?;
+    let wal = format!("{}-wal", path.display());
+    for (_, segment) in files(dir.join("wal"), "wal")?
+        .into_iter()
+        .filter(|(at, _)| *at > snapshot_at && *at <= until)
+    {
+        std::fs::copy(segment, &wal)?;
+        let conn = rusqlite::Connection::open(path)?;
+        conn.query_row("pragma wal_checkpoint(truncate)", [], |_| Ok(()))?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -46,6 +668,13 @@ mod tests {
             limit 1
         "# as Post;
 
+        let quoted_posts = r#"
+            select posts.*
+            from posts
+            where title = 'it''s' or title = ?
+            order by id
+        "# as Vec<Post>;
+
         let like_post = r#"
             insert into likes (post_id)
             values (?)
@@ -119,14 +748,323 @@ mod tests {
             do nothing
             returning *
         "# as Post;
+
+        let insert_named_post = r#"
+            insert into posts (title, test)
+            values (:title, ?)
+            returning *
+        "# as Post;
+
+        let posts_between = r#"
+            select posts.*
+            from posts
+            where posts.id between :start and :end
+            or posts.test = :start
+            order by posts.id
+        "# as Vec<Post>;
+
+        let post_stats = r#"
+            select
+                posts.id,
+                count(likes.id) as like_count,
+                sum(likes.id) as like_sum,
+                avg(likes.id) as like_avg,
+                coalesce(posts.test, 0) as test,
+                upper(posts.title) as title,
+                case when posts.test > 1 then 'big' else 'small' end as size,
+                cast(posts.id as real) / 2 as half
+            from posts
+            join likes on likes.post_id = posts.id
+            group by posts.id
+            order by posts.id
+        "#;
+
+        let posts_with_likes = r#"
+            select
+                posts.id,
+                likes.id as like_id,
+                nullif(posts.id, 0) as "post_id!",
+                posts.title as "title?"
+            from posts
+            left join likes on likes.post_id = posts.id
+            where likes.id is not null or posts.id = ?
+            order by posts.id
+        "#;
+
+        let all_likes = r#"
+            select *
+            from likes
+            order by id
+        "#;
+
+        let numbers = r#"
+            with recursive numbers(n) as (
+                select 1
+                union all
+                select n + 1 from numbers where n < ?
+            )
+            select n from numbers
+        "#;
+
+        let post_feed = r#"
+            with liked as (
+                select likes.post_id, count(*) as like_count
+                from likes
+                group by likes.post_id
+            )
+            select
+                posts.id,
+                posts.title,
+                liked.like_count,
+                (select max(likes.id) from likes where likes.post_id = posts.id) as last_like_id
+            from posts
+            left join liked on liked.post_id = posts.id
+            where posts.id > ?
+            union all
+            select oldest.id, oldest.title, 0, null
+            from (select posts.id, posts.title from posts where posts.title = :title) as oldest
+            where oldest.id = (select min(posts.id) from posts)
+            order by 1
+        "#;
+
+        #[types(email = Email)]
+        let create_events = r#"
+            create table if not exists events (
+                id integer primary key not null,
+                published boolean not null,
+                starts_at datetime not null,
+                ends_on date,
+                data json not null,
+                email text not null
+            )"# as Event;
+
+        let insert_event = r#"
+            insert into events (published, starts_at, ends_on, data, email)
+            values (?, ?, ?, ?, ?)
+            returning *
+        "# as Event;
+
+        let events_in = r#"
+            select *
+            from events
+            where starts_at in (?) and email in (?)
+        "# as Vec<Event>;
+
+        let create_drafts = r#"
+            create table if not exists drafts (
+                id integer primary key not null,
+                status text not null check (status in ('draft', 'in-review', 'published')),
+                previous_status text,
+                check (previous_status in ('draft', 'in-review'))
+            )"# as Draft;
+
+        let insert_draft = r#"
+            insert into drafts (status, previous_status)
+            values (?, ?)
+            returning *
+        "# as Draft;
+
+        let drafts_by_status = r#"
+            select drafts.*
+            from drafts
+            where drafts.status = ?
+        "# as Vec<Draft>;
+
+        let create_todos = r#"
+            create table if not exists todos (
+                id integer primary key not null,
+                content text not null default 'todo',
+                priority integer not null default -1,
+                done boolean not null default false,
+                state text not null default 'open' check (state in ('open', 'closed')),
+                created_at datetime not null default current_timestamp,
+                note text default 'none'
+            )"# as Todo;
+
+        let insert_todo = r#"
+            insert into todos (content, priority, done, state, created_at, note)
+            values (?, ?, ?, ?, ?, ?)
+            returning *
+        "# as Todo;
+
+        let upsert_todo = r#"
+            insert into todos (id, content, priority)
+            values (?, ?, ?) -- defaults fill in None
+            on conflict (id) do update
+            set content = excluded.content, priority = excluded.priority + 1
+            returning *
+        "# as Todo;
+
+        #[crud]
+        let create_notes = r#"
+            create table if not exists notes (
+                id integer primary key not null,
+                body text not null,
+                pinned boolean not null default false,
+                color text
+            )"# as Note;
+
+        #[crud]
+        let create_tags = r#"
+            create table if not exists tags (
+                note_id integer not null references notes(id),
+                name text not null,
+                color text,
+                primary key (note_id, name)
+            )"# as Tag;
+
+        #[crud(plural = "categories")]
+        let create_categories = r#"
+            create table if not exists categories (
+                id integer primary key not null,
+                name text not null,
+                parent_id integer references categories(id)
+            )"# as Category;
+
+        #[crud(plural = "entries")]
+        let create_entries = r#"
+            create table if not exists entries (
+                id integer primary key not null,
+                category_id integer not null references categories(id),
+                title text not null
+            )"# as Entry;
+
+        let all_notes = r#"
+            select *
+            from notes
+            order by id desc
+        "# as Vec<Note>;
+
+        let posts_by_ids = r#"
+            select posts.*
+            from posts
+            where posts.id = ?2 or posts.id = ?1
+            order by posts.id
+        "# as Vec<Post>;
+
+        #[functions(shout)]
+        let loud_post = r#"
+            select posts.id, cast(shout(posts.title) as text) as "loud!"
+            from posts
+            where posts.id = ?
+            limit 1
+        "#;
+
+        let paged_posts = r#"
+            select *
+            from posts
+            order by id
+            limit ? offset ?
+        "# as Vec<Post>;
+
+        let liked_posts = r#"
+            select posts.id, count(likes.id) as like_count
+            from posts
+            join likes on likes.post_id = posts.id
+            where posts.title != ?
+            group by posts.id
+            having count(*) > ? and like_count < 100
+            order by posts.id
+        "#;
+
+        let posts_in = r#"
+            select posts.*
+            from posts
+            where posts.id in (?) and posts.title not in (:skip)
+            order by posts.id
+        "# as Vec<Post>;
+
+        let count_posts_in = r#"
+            select count(*)
+            from posts
+            where id in (?)
+        "#;
+
+        let retitle_posts = r#"
+            update posts
+            set title = ?
+            where id in (?)
+        "#;
+
+        let delete_posts = r#"
+            delete from posts
+            where id in (?)
+        "#;
+
+        #[triggers]
+        let create_posts_fts = r#"
+            create virtual table if not exists posts_fts using fts5(
+                title,
+                content='posts',
+                content_rowid='id'
+            )"# as PostSearch;
+
+        let search_posts = r#"
+            select posts.*, rank
+            from posts_fts
+            join posts on posts.id = posts_fts.rowid
+            where posts_fts match ?
+            order by rank
+        "#;
+
+        let search_titles = r#"
+            select
+                rowid as id,
+                highlight(posts_fts, 0, '[', ']') as title,
+                snippet(posts_fts, 0, '[', ']', '...', 2) as snippet,
+                bm25(posts_fts) as score
+            from posts_fts
+            where title match :query
+            order by rank
+        "#;
+
+        let create_post_stats = r#"
+            create view if not exists post_stats as
+            select posts.id, posts.title, count(likes.id) as like_count
+            from posts
+            left join likes on likes.post_id = posts.id
+            group by posts.id
+        "# as PostStat;
+
+        let popular_posts = r#"
+            select *
+            from post_stats
+            where like_count >= ?
+            order by like_count desc, id
+        "# as Vec<PostStat>;
+
+        let post_like_count = r#"
+            select title, like_count
+            from post_stats
+            where id = ?
+            limit 1
+        "#;
+    }
+
+    #[derive(Default, Debug, Deserialize, Serialize, Clone, PartialEq)]
+    #[serde(crate = "crate::serde")]
+    pub struct Email(String);
+
+    impl rusqlite::ToSql for Email {
+        fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+            self.0.to_sql()
+        }
+    }
+
+    impl rusqlite::types::FromSql for Email {
+        fn column_result(
+            value: rusqlite::types::ValueRef<'_>,
+        ) -> rusqlite::types::FromSqlResult<Self> {
+            Ok(Email(String::column_result(value)?))
+        }
     }
 
     #[test]
     async fn it_works() -> ryde::Result<()> {
         let db = db(":memory:").await?;
-        let _ = db.create_posts().await?;
-        let _ = db.create_likes().await?;
-        let _ = db.create_items().await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        db.create_items().await?;
         let new_post = db.insert_post("title".into(), Some(1)).await?;
         assert_eq!(new_post.title, "title");
         assert_eq!(new_post.test, Some(1));
@@ -163,11 +1101,999 @@ mod tests {
         assert_eq!(10, items.last().unwrap().value);
 
         let post = db.create_post(1, String::default()).await?;
-        assert_eq!(true, post.is_some());
+        assert!(post.is_some());
 
         let post = db.create_post(1, String::default()).await?;
         assert_eq!(None, post);
 
         Ok(())
     }
+
+    #[test]
+    async fn named_params_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        for test in [2, 0, 0, 0] {
+            db.insert_named_post("title".into(), Some(test)).await?;
+        }
+
+        let posts = db.posts_between(2, 3).await?;
+        let ids = posts.iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        let posts = db.posts_by_ids(1, 3).await?;
+        let ids = posts.iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn escaped_quotes_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        let quoted = db.insert_post("it's".into(), None).await?;
+        db.insert_post("its".into(), None).await?;
+        let other = db.insert_post("other".into(), None).await?;
+
+        assert_eq!(db.quoted_posts("other".into()).await?, vec![quoted, other]);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn limit_and_having_params_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        let rows = (1..=5).map(|i| (format!("post {}", i), None)).collect();
+        let posts = db.insert_post_many(rows).await?;
+
+        let page = db.paged_posts(2, 1).await?;
+        assert_eq!(page, posts[1..3].to_vec());
+        assert_eq!(db.paged_posts(2, 4).await?, posts[4..].to_vec());
+
+        db.like_post(posts[0].id).await?;
+        db.like_post(posts[1].id).await?;
+        db.like_post(posts[1].id).await?;
+        let liked = db.liked_posts("".into(), 1).await?;
+        assert_eq!(liked.len(), 1);
+        assert_eq!((liked[0].id, liked[0].like_count), (posts[1].id, 2));
+        assert_eq!(db.liked_posts("".into(), 0).await?.len(), 2);
+        assert!(db.liked_posts("post 2".into(), 1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    async fn list_params_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        for title in ["a", "b", "c", "d"] {
+            db.insert_post(title.into(), None).await?;
+        }
+
+        let posts = db.posts_in(vec![1, 2, 4], vec!["b".into()]).await?;
+        let ids = posts.iter().map(|p| p.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 4]);
+        assert_eq!(db.count_posts_in(vec![2, 3]).await?, 2);
+        assert_eq!(db.count_posts_in(vec![]).await?, 0);
+
+        assert_eq!(db.retitle_posts("z".into(), vec![1, 2]).await?, 2);
+        assert_eq!(db.posts_in(vec![1, 2], vec![]).await?[0].title, "z");
+        assert_eq!(db.delete_posts(vec![1, 2, 3]).await?, 3);
+        assert_eq!(db.post_count().await?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn insert_many_works() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+
+        let rows = (0..100).map(|i| (format!("post {}", i), Some(i))).collect();
+        let posts = db.insert_post_many(rows).await?;
+        assert_eq!(posts.len(), 100);
+        assert_eq!(posts[99].title, "post 99");
+        assert_eq!(db.post_count().await?, 100);
+
+        let likes = db.like_post_many(vec![posts[0].id, posts[1].id]).await?;
+        assert_eq!(likes.len(), 2);
+
+        // the whole batch is rolled back when one row fails
+        let result = db.like_post_many(vec![posts[2].id, 1000]).await;
+        assert!(result.is_err());
+        assert_eq!(db.likes_for_post(posts[2].id).await?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn expression_types_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        let post = db.insert_post("title".into(), None).await?;
+        db.like_post(post.id).await?;
+        db.like_post(post.id).await?;
+
+        let stats = db.post_stats().await?;
+        assert_eq!(
+            stats,
+            vec![PostStats {
+                id: 1,
+                like_count: 2,
+                like_sum: Some(3),
+                like_avg: Some(1.5),
+                test: 0,
+                title: "TITLE".into(),
+                size: "small".into(),
+                half: 0.5,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    async fn left_join_works() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        let liked = db.insert_post("liked".into(), None).await?;
+        let post = db.insert_post("title".into(), None).await?;
+        let like = db.like_post(liked.id).await?;
+
+        let rows = db.posts_with_likes(post.id).await?;
+        assert_eq!(
+            rows,
+            vec![
+                PostsWithLikes {
+                    id: liked.id,
+                    like_id: Some(like.id),
+                    post_id: liked.id,
+                    title: Some("liked".into()),
+                },
+                PostsWithLikes {
+                    id: post.id,
+                    like_id: None,
+                    post_id: post.id,
+                    title: Some("title".into()),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    async fn wildcard_works() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        let post = db.insert_post("title".into(), None).await?;
+        let like = db.like_post(post.id).await?;
+
+        let likes = db.all_likes().await?;
+        assert_eq!(
+            likes,
+            vec![AllLikes {
+                id: like.id,
+                post_id: post.id
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    async fn subqueries_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        let first = db.insert_post("first".into(), None).await?;
+        let second = db.insert_post("second".into(), None).await?;
+        let like = db.like_post(second.id).await?;
+
+        let numbers = db.numbers(3).await?;
+        let numbers = numbers.iter().map(|row| row.n).collect::<Vec<_>>();
+        assert_eq!(numbers, vec![1, 2, 3]);
+
+        let feed = db.post_feed(first.id, "first".into()).await?;
+        assert_eq!(
+            feed,
+            vec![
+                PostFeed {
+                    id: first.id,
+                    title: "first".into(),
+                    like_count: Some(0),
+                    last_like_id: None,
+                },
+                PostFeed {
+                    id: second.id,
+                    title: "second".into(),
+                    like_count: Some(1),
+                    last_like_id: Some(like.id),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    async fn type_mappings_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_events().await?;
+        let starts_at = time::macros::datetime!(2024-05-01 12:30 UTC);
+        let ends_on = time::macros::date!(2024 - 05 - 02);
+        let data = serde_json::json!({ "tags": ["rust"] });
+        let email = Email("a@example.com".into());
+
+        let event = db
+            .insert_event(true, starts_at, Some(ends_on), data.clone(), email.clone())
+            .await?;
+        assert!(event.published);
+        assert_eq!(event.starts_at, starts_at);
+        assert_eq!(event.ends_on, Some(ends_on));
+        assert_eq!(event.data, data);
+        assert_eq!(event.email, email);
+        assert_eq!(Event::default().starts_at, time::OffsetDateTime::UNIX_EPOCH);
+
+        let events = db
+            .events_in(
+                vec![starts_at, ends_on.midnight().assume_utc()],
+                vec![email],
+            )
+            .await?;
+        assert_eq!(events, vec![event]);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn check_enums_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_drafts().await?;
+        let draft = db
+            .insert_draft(DraftStatus::InReview, Some(DraftPreviousStatus::Draft))
+            .await?;
+        assert_eq!(draft.status, DraftStatus::InReview);
+
+        let drafts = db.drafts_by_status(DraftStatus::InReview).await?;
+        assert_eq!(drafts, vec![draft]);
+        assert_eq!(DraftStatus::InReview.to_string(), "in-review");
+        assert_eq!("published".parse(), Ok(DraftStatus::Published));
+        assert!("archived".parse::<DraftStatus>().is_err());
+        assert_eq!(Draft::default().status, DraftStatus::Draft);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn defaults_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_todos().await?;
+
+        let todo = db.insert_todo(None, None, None, None, None, None).await?;
+        assert_eq!(todo.content, "todo");
+        assert_eq!(todo.priority, -1);
+        assert!(!todo.done);
+        assert_eq!(todo.state, TodoState::Open);
+        assert_eq!(todo.note, None);
+
+        let todo = db
+            .insert_todo(
+                Some("write".into()),
+                Some(2),
+                Some(true),
+                None,
+                None,
+                Some("x".into()),
+            )
+            .await?;
+        assert_eq!(todo.content, "write");
+        assert_eq!(todo.priority, 2);
+        assert!(todo.done);
+
+        let todo = db.upsert_todo(10, None, None).await?;
+        assert_eq!((todo.content.as_str(), todo.priority), ("todo", -1));
+        let todo = db.upsert_todo(10, Some("again".into()), None).await?;
+        assert_eq!(
+            (todo.id, todo.content.as_str(), todo.priority),
+            (10, "again", 0)
+        );
+
+        let todo = Todo::default();
+        assert_eq!(todo.content, "todo");
+        assert_eq!(todo.priority, -1);
+        assert_eq!(todo.state, TodoState::Open);
+        assert_eq!(todo.note, Some("none".into()));
+        assert!(todo.created_at > time::OffsetDateTime::UNIX_EPOCH);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn crud_works() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_notes().await?;
+
+        let first = db.insert_note("first".into(), None, None).await?;
+        assert!(!first.pinned);
+        let second = db
+            .insert_note("second".into(), Some(true), Some("red".into()))
+            .await?;
+        assert_eq!(db.note_by_id(second.id).await?, Some(second.clone()));
+
+        let notes = db.all_notes().await?;
+        assert_eq!(notes, vec![second.clone(), first.clone()]);
+
+        let first = db
+            .update_note("edited".into(), true, None, first.id)
+            .await?;
+        assert_eq!(first.body, "edited");
+        assert!(first.pinned);
+
+        // a missing id is NotFound and leaves the writer usable
+        assert_eq!(
+            db.update_note("gone".into(), false, None, 999).await,
+            Err(ryde::Error::NotFound)
+        );
+        assert_eq!(db.note_by_id(999).await?, None);
+        let third = db.insert_note("third".into(), None, None).await?;
+        assert_eq!(third.body, "third");
+
+        let deleted = db.delete_note(second.id).await?;
+        assert_eq!(deleted, Some(second.clone()));
+        assert_eq!(db.note_by_id(second.id).await?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn crud_composite_key_works() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_notes().await?;
+        db.create_tags().await?;
+        let note = db.insert_note("note".into(), None, None).await?;
+        let red = db.insert_tag(note.id, "red".into(), None).await?;
+        let blue = db
+            .insert_tag(note.id, "blue".into(), Some("#00f".into()))
+            .await?;
+        assert_eq!(db.all_tags().await?, vec![blue.clone(), red.clone()]);
+        assert_eq!(db.tag_by_id(note.id, "red".into()).await?, Some(red));
+
+        let red = db
+            .update_tag(Some("#f00".into()), note.id, "red".into())
+            .await?;
+        assert_eq!(red.color, Some("#f00".into()));
+        assert_eq!(
+            db.update_tag(None, note.id, "green".into()).await,
+            Err(ryde::Error::NotFound)
+        );
+
+        let deleted = db.delete_tag(note.id, "blue".into()).await?;
+        assert_eq!(deleted, Some(blue));
+        let tags = db.all_tags().await?;
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].name, "red");
+
+        Ok(())
+    }
+
+    #[test]
+    async fn relations_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+
+        let first = db.insert_post("first".into(), None).await?;
+        let second = db.insert_post("second".into(), None).await?;
+        let third = db.insert_post("third".into(), None).await?;
+        let like1 = db.like_post(first.id).await?;
+        let like2 = db.like_post(first.id).await?;
+        let like3 = db.like_post(second.id).await?;
+
+        assert_eq!(
+            db.likes_for_post(first.id).await?,
+            vec![like1.clone(), like2.clone()]
+        );
+        assert_eq!(db.likes_for_post(third.id).await?, vec![]);
+        assert_eq!(db.post_for_like(&like3).await?, Some(second.clone()));
+
+        let posts = vec![first, second, third];
+        let likes = db.likes_for_posts(&posts).await?;
+        assert_eq!(likes, vec![like1, like2, like3]);
+        assert_eq!(db.likes_for_posts(&[]).await?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn plurals_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_categories().await?;
+        db.create_entries().await?;
+
+        let rust = db.insert_category("rust".into(), None).await?;
+        let macros = db.insert_category("macros".into(), Some(rust.id)).await?;
+        let entry = db.insert_entry(macros.id, "db!".into()).await?;
+
+        assert_eq!(
+            db.all_categories().await?,
+            vec![rust.clone(), macros.clone()]
+        );
+        assert_eq!(db.all_entries().await?, vec![entry.clone()]);
+        assert_eq!(
+            db.categories_for_parents(std::slice::from_ref(&rust))
+                .await?,
+            vec![macros.clone()]
+        );
+        assert_eq!(
+            db.entries_for_categories(&[rust, macros.clone()]).await?,
+            vec![entry.clone()]
+        );
+        assert_eq!(db.category_for_entry(&entry).await?, Some(macros));
+
+        Ok(())
+    }
+
+    #[test]
+    async fn statement_cache_works() -> ryde::Result<()> {
+        let db = db_with(":memory:", DbOptions::new().statement_cache_capacity(2)).await?;
+        db.create_posts().await?;
+        let post = db.insert_post("cached".into(), None).await?;
+        for _ in 0..3 {
+            assert_eq!(db.select_post(post.id).await?, Some(post.clone()));
+            assert_eq!(db.post_count().await?, 1);
+            assert_eq!(db.posts_in(vec![post.id], vec![]).await?.len(), 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    async fn options_work() -> ryde::Result<()> {
+        let options = DbOptions::new()
+            .busy_timeout(std::time::Duration::from_millis(1500))
+            .cache_size(-4000)
+            .temp_store("memory")
+            .init_sql("create temp table visits (path text not null)")
+            .function("shout", 1, |ctx| Ok(ctx.get::<String>(0)?.to_uppercase()));
+        let db = db_with(":memory:", options).await?;
+        db.create_posts().await?;
+        let post = db.insert_post("quiet".into(), None).await?;
+        let loud = db.loud_post(post.id).await?.unwrap();
+        assert_eq!(loud.loud, "QUIET");
+
+        let (busy_timeout, cache_size, temp_store, visits) = db
+            .writer
+            .call(|conn| {
+                let pragma = |name: &str| conn.pragma_query_value(None, name, |row| row.get(0));
+                let visits: i64 =
+                    conn.query_row("select count(*) from temp.visits", [], |row| row.get(0))?;
+                Ok((
+                    pragma("busy_timeout")?,
+                    pragma("cache_size")?,
+                    pragma("temp_store")?,
+                    visits,
+                ))
+            })
+            .await?;
+        assert_eq!(
+            (busy_timeout, cache_size, temp_store, visits),
+            (1500, -4000, 2, 0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    async fn constraint_errors_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        db.create_drafts().await?;
+        let post = db.insert_post("first".into(), None).await?;
+
+        let execute = |sql: &'static str| {
+            db.writer
+                .call(move |conn| Ok(conn.execute(sql, []).map_err(ryde::Error::from)))
+        };
+        assert_eq!(
+            execute("insert into posts (id, title) values (1, 'again')").await?,
+            Err(ryde::Error::UniqueConstraintFailed("posts.id".into()))
+        );
+        assert_eq!(
+            execute("insert into posts (title) values (null)").await?,
+            Err(ryde::Error::NotNullConstraintFailed("posts.title".into()))
+        );
+        assert!(matches!(
+            execute("insert into drafts (status) values ('lost')").await?,
+            Err(ryde::Error::CheckConstraintFailed(_))
+        ));
+        assert_eq!(
+            db.like_post(post.id + 1).await,
+            Err(ryde::Error::ForeignKeyConstraintFailed)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    async fn backups_work() -> ryde::Result<()> {
+        let dir = std::env::temp_dir().join(format!("ryde-backups-{}", std::process::id()));
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        let rows = (0..500).map(|i| ("x".repeat(100 + i), None)).collect();
+        db.insert_post_many(rows).await?;
+        let count = |path: std::path::PathBuf| -> ryde::Result<i64> {
+            let conn = rusqlite::Connection::open(path)?;
+            Ok(conn.query_row("select count(*) from posts", [], |row| row.get(0))?)
+        };
+
+        std::fs::create_dir_all(&dir)?;
+        let steps = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = steps.clone();
+        db.backup_with_progress(dir.join("copy.db"), move |progress| {
+            assert!(progress.remaining <= progress.pagecount);
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        })
+        .await?;
+        assert!(steps.load(std::sync::atomic::Ordering::Relaxed) > 0);
+        assert_eq!(count(dir.join("copy.db"))?, 500);
+
+        db.snapshot(dir.join("snapshot.db")).await?;
+        assert_eq!(count(dir.join("snapshot.db"))?, 500);
+        assert!(db.snapshot(dir.join("snapshot.db")).await.is_err());
+
+        let rotated = dir.join("rotated");
+        for _ in 0..4 {
+            backup_rotate(&db.writer, &rotated, 2).await?;
+        }
+        let backups = std::fs::read_dir(&rotated)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        assert_eq!(backups.len(), 2);
+        assert_eq!(count(backups[0].clone())?, 500);
+
+        let periodic = dir.join("periodic");
+        // the first backup happens right away
+        let task = db.backup_every(&periodic, std::time::Duration::from_secs(3600), 2);
+        while !periodic.exists()
+            || !std::fs::read_dir(&periodic)?
+                .flatten()
+                .any(|entry| entry.path().extension().is_some_and(|ext| ext == "db"))
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        task.abort();
+
+        // copy.db is a file so the backup directory can't be created
+        let failed = db.backup_every(
+            dir.join("copy.db").join("periodic"),
+            Duration::from_secs(3600),
+            2,
+        );
+        assert!(matches!(failed.await, Ok(Err(ryde::Error::Io(_)))));
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    async fn replication_works() -> ryde::Result<()> {
+        let dir = std::env::temp_dir().join(format!("ryde-replica-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("live.db");
+        let replica = dir.join("replica");
+        let memory = db(":memory:").await?;
+        assert!(replicate_once(&memory.writer, &replica, true)
+            .await
+            .is_err());
+
+        let options = DbOptions::new().busy_timeout(Duration::from_millis(10));
+        let db = db_with(&path, options).await?;
+        db.create_posts().await?;
+        let count = |path: std::path::PathBuf| -> ryde::Result<i64> {
+            let conn = rusqlite::Connection::open(path)?;
+            Ok(conn.query_row("select count(*) from posts", [], |row| row.get(0))?)
+        };
+        let insert = |n: usize| {
+            let rows = (0..n).map(|i| (format!("post {}", i), None)).collect();
+            db.insert_post_many(rows)
+        };
+
+        insert(3).await?;
+        replicate_once(&db.writer, &replica, true).await?;
+        insert(2).await?;
+        replicate_once(&db.writer, &replica, false).await?;
+        let before = time::OffsetDateTime::now_utc();
+        insert(1).await?;
+        replicate_once(&db.writer, &replica, false).await?;
+        replicate_once(&db.writer, &replica, true).await?;
+        insert(4).await?;
+        // an open read transaction keeps the wal from being truncated
+        let reader = rusqlite::Connection::open(&path)?;
+        reader.execute_batch("begin; select count(*) from posts;")?;
+        let segments = std::fs::read_dir(replica.join("wal"))?.count();
+        assert_eq!(
+            replicate_once(&db.writer, &replica, false).await,
+            Err(ryde::Error::DatabaseBusy)
+        );
+        assert_eq!(std::fs::read_dir(replica.join("wal"))?.count(), segments);
+        reader.execute_batch("commit")?;
+        replicate_once(&db.writer, &replica, false).await?;
+
+        restore(&replica, dir.join("latest.db"), None)?;
+        assert_eq!(count(dir.join("latest.db"))?, 10);
+        restore(&replica, dir.join("before.db"), Some(before))?;
+        assert_eq!(count(dir.join("before.db"))?, 5);
+        assert!(restore(&replica, dir.join("before.db"), None).is_err());
+
+        // the task turns automatic checkpoints off until it's aborted
+        let autocheckpoint = || {
+            db.writer.call(|conn| {
+                Ok(conn
+                    .pragma_query_value(None, "wal_autocheckpoint", |row| row.get::<_, i64>(0))?)
+            })
+        };
+        assert_eq!(autocheckpoint().await?, 1000);
+        let task = db.replicate(
+            &replica,
+            Duration::from_millis(10),
+            Duration::from_secs(3600),
+        );
+        while autocheckpoint().await? != 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        task.abort();
+        while autocheckpoint().await? != 1000 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        drop(db);
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    async fn readers_work() -> ryde::Result<()> {
+        let memory = db(":memory:").await?;
+        assert!(memory.readers.is_empty());
+
+        let path = std::env::temp_dir().join(format!("ryde-readers-{}.db", std::process::id()));
+        let options = DbOptions::new()
+            .readers(2)
+            .init_sql("create table if not exists visits (path text not null)")
+            .reader_init_sql("pragma cache_size = -2000");
+        let db = db_with(&path, options).await?;
+        db.create_posts().await?;
+        assert_eq!(db.readers.len(), 2);
+        let cache_size: i64 = db.readers[1]
+            .call(|conn| Ok(conn.pragma_query_value(None, "cache_size", |row| row.get(0))?))
+            .await?;
+        assert_eq!(cache_size, -2000);
+
+        let post = db.insert_post("read".into(), None).await?;
+        for _ in 0..4 {
+            assert_eq!(db.select_post(post.id).await?, Some(post.clone()));
+        }
+        let written = db.readers[0]
+            .call(|conn| Ok(conn.execute("delete from posts", [])))
+            .await?;
+        assert!(written.is_err());
+        assert_eq!(db.post_count().await?, 1);
+
+        let options = DbOptions::new()
+            .read_only(true)
+            .init_sql("create temp table visits (path text not null)");
+        let read_only = db_with(&path, options).await?;
+        assert_eq!(read_only.post_count().await?, 1);
+        let visits = read_only
+            .writer
+            .call(|conn| {
+                Ok(
+                    conn.query_row("select count(*) from temp.visits", [], |row| {
+                        row.get::<_, i64>(0)
+                    })?,
+                )
+            })
+            .await?;
+        assert_eq!(visits, 0);
+        assert_eq!(
+            read_only.insert_post("nope".into(), None).await,
+            Err(ryde::Error::DatabaseReadOnly)
+        );
+
+        drop(db);
+        drop(read_only);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        Ok(())
+    }
+
+    // cargo test prepared_statements_bench -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    async fn prepared_statements_bench() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        let rows = (0..100).map(|i| (format!("post {}", i), Some(i))).collect();
+        db.insert_post_many(rows).await?;
+        let sql = "select posts.* from posts where id = ? limit 1";
+        let (prepared, cached) = db
+            .writer
+            .call(move |conn| {
+                let run = || -> ryde::Result<(std::time::Duration, std::time::Duration)> {
+                    let start = std::time::Instant::now();
+                    for id in 0..10_000 {
+                        let mut stmt = conn.prepare(sql)?;
+                        let _ = stmt.query_row([id % 100 + 1], Post::new)?;
+                    }
+                    let prepared = start.elapsed();
+                    let start = std::time::Instant::now();
+                    for id in 0..10_000 {
+                        Tx(conn).select_post(id % 100 + 1)?;
+                    }
+
+                    Ok((prepared, start.elapsed()))
+                };
+
+                Ok(run())
+            })
+            .await??;
+        println!("prepare: {:?}, prepare_cached: {:?}", prepared, cached);
+        assert!(cached < prepared);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn transaction_works() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+
+        let like = db
+            .transaction(|tx| {
+                let post = tx.insert_post("title".into(), None)?;
+                tx.like_post(post.id)
+            })
+            .await?;
+        assert_eq!(like.post_id, 1);
+
+        let result = db
+            .transaction(|tx| {
+                tx.insert_post("rolled back".into(), None)?;
+                Err::<(), _>(ryde::Error::NotFound)
+            })
+            .await;
+        assert_eq!(result, Err(ryde::Error::NotFound));
+
+        db.transaction(|tx| {
+            tx.insert_post("outer".into(), None)?;
+            let inner = tx.transaction(|tx| {
+                tx.insert_post("inner".into(), None)?;
+                Err::<(), _>(ryde::Error::NotFound)
+            });
+            assert!(inner.is_err());
+            Ok(())
+        })
+        .await?;
+
+        let posts = db.select_posts().await?;
+        let titles = posts.iter().map(|p| p.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, vec!["title", "outer"]);
+
+        Ok(())
+    }
+
+    #[test]
+    async fn full_text_search_works() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_posts_fts().await?;
+        let rust = db.insert_post("learning rust".into(), None).await?;
+        let sqlite = db
+            .insert_post("sqlite full text search".into(), None)
+            .await?;
+        db.insert_post("nothing to see".into(), None).await?;
+
+        let posts = db.search_posts("rust".into()).await?;
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].id, rust.id);
+        assert_eq!(posts[0].title, "learning rust");
+
+        let titles = db.search_titles("search".into()).await?;
+        assert_eq!(titles.len(), 1);
+        assert_eq!(titles[0].id, sqlite.id);
+        assert_eq!(titles[0].title, "sqlite full text [search]");
+        assert_eq!(titles[0].snippet, "...text [search]");
+        assert!(titles[0].score < 0.0);
+
+        // the triggers keep the index in sync with posts
+        db.update_post("learning sqlite".into(), None, rust.id)
+            .await?;
+        db.delete_post(sqlite.id).await?;
+        assert!(db.search_posts("rust".into()).await?.is_empty());
+        let posts = db.search_posts("sqlite".into()).await?;
+        assert_eq!(
+            posts.iter().map(|post| post.id).collect::<Vec<_>>(),
+            vec![rust.id]
+        );
+        assert_eq!(PostSearch::default().title, "");
+
+        Ok(())
+    }
+
+    #[test]
+    async fn views_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        db.create_post_stats().await?;
+        let first = db.insert_post("first".into(), None).await?;
+        let second = db.insert_post("second".into(), None).await?;
+        db.like_post(second.id).await?;
+        db.like_post(second.id).await?;
+        db.like_post(first.id).await?;
+
+        let posts = db.popular_posts(1).await?;
+        assert_eq!(
+            posts,
+            vec![
+                PostStat {
+                    id: second.id,
+                    title: "second".into(),
+                    like_count: 2,
+                },
+                PostStat {
+                    id: first.id,
+                    title: "first".into(),
+                    like_count: 1,
+                },
+            ]
+        );
+        let post = db.post_like_count(second.id).await?;
+        assert_eq!(
+            post,
+            Some(PostLikeCount {
+                title: "second".into(),
+                like_count: 2,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    async fn changes_are_sent_after_commit() -> ryde::Result<()> {
+        let path = std::env::temp_dir().join(format!("ryde-changes-{}.db", std::process::id()));
+        let db = db_with(&path, DbOptions::new().readers(2)).await?;
+        db.create_posts().await?;
+        let mut posts = db.subscribe("posts");
+
+        for i in 0..200 {
+            db.insert_post(format!("post {}", i), None).await?;
+            let change = posts.try_recv().expect("a change once the insert returns");
+            assert!(db.select_post(change.rowid).await?.is_some());
+        }
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    async fn changes_work() -> ryde::Result<()> {
+        let db = db(":memory:").await?;
+        db.create_posts().await?;
+        db.create_likes().await?;
+        let mut posts = db.subscribe("posts");
+        let mut likes = db.subscribe("likes");
+
+        let post = db.insert_post("first".into(), None).await?;
+        db.update_post("second".into(), None, post.id).await?;
+        let _ = db
+            .transaction(|tx| {
+                tx.insert_post("rolled back".into(), None)?;
+                Err::<(), _>(ryde::Error::NotFound)
+            })
+            .await;
+        db.transaction(|tx| {
+            tx.insert_post("outer".into(), None)?;
+            let _ = tx.transaction(|tx| {
+                tx.insert_post("inner".into(), None)?;
+                Err::<(), _>(ryde::Error::NotFound)
+            });
+            Ok(())
+        })
+        .await?;
+        // the failed insert is undone by itself and the transaction still commits
+        db.transaction(|tx| {
+            assert_eq!(
+                tx.like_post(999),
+                Err(ryde::Error::ForeignKeyConstraintFailed)
+            );
+            Ok(())
+        })
+        .await?;
+        db.delete_post(post.id).await?;
+
+        let change = |action, rowid| Change {
+            action,
+            table: "posts".into(),
+            rowid,
+        };
+        let mut received = vec![];
+        while let Ok(change) = posts.try_recv() {
+            received.push(change);
+        }
+        assert_eq!(
+            received,
+            vec![
+                change(Action::Insert, 1),
+                change(Action::Update, 1),
+                change(Action::Insert, 2),
+                change(Action::Delete, 1),
+            ]
+        );
+        assert!(likes.try_recv().is_err());
+
+        Ok(())
+    }
+
+    mod migrations {
+        use super::*;
+        use tokio::test;
+
+        db! {
+            let migrate = [
+                r#"
+                create table posts (
+                    id integer primary key not null,
+                    title text not null
+                )"# as Post,
+                r#"alter table posts add column body text"#,
+            ];
+
+            let insert_post = r#"
+                insert into posts (title, body)
+                values (?, ?)
+                returning *
+            "# as Post;
+        }
+
+        #[test]
+        async fn migrate_works() -> ryde::Result<()> {
+            let db = db(":memory:").await?;
+            db.migrate().await?;
+            db.migrate().await?;
+
+            let post = db.insert_post("title".into(), Some("body".into())).await?;
+            assert_eq!(post.title, "title");
+            assert_eq!(post.body, Some("body".into()));
+
+            db.writer
+                .call(|conn| {
+                    conn.pragma_update(None, "user_version", 3)
+                        .map_err(|err| err.into())
+                })
+                .await?;
+            assert!(matches!(db.migrate().await, Err(ryde::Error::Migration(_))));
+
+            Ok(())
+        }
+    }
 }
@@ -256,7 +256,7 @@ mod tests {
             html! { <li>{elements}</li> }
         }
 
-        let items = vec![1, 2, 3];
+        let items = [1, 2, 3];
 
         let component = html! { <List>{items.iter().map(|i| html! { <Item>{i}</Item> }).collect::<Vec<_>>()}</List> };
 
@@ -306,7 +306,7 @@ where
     T: Render,
 {
     fn render_to_string(&self, buffer: &mut String) {
-        for item in self.clone().into_iter() {
+        for item in self.clone() {
             item.render_to_string(buffer);
         }
     }
@@ -399,9 +399,8 @@ where
     T: Render,
 {
     fn render_to_string(&self, buffer: &mut String) {
-        match self {
-            Some(t) => t.render_to_string(buffer),
-            None => {}
+        if let Some(t) = self {
+            t.render_to_string(buffer)
         }
     }
 }
@@ -1,6 +1,6 @@
 extern crate self as ryde;
 
-mod db;
+pub mod db;
 mod html;
 mod router;
 
@@ -16,7 +16,7 @@ pub use axum::{
 };
 pub use axum_extra::{self, extract::*, headers};
 pub use cookie::Cookie;
-pub use db::{db, rusqlite, tokio_rusqlite, Connection};
+pub use db::{db, rusqlite, tokio_rusqlite, Connection, DbOptions};
 pub use html::{component, escape, html, Component, Elements, Render};
 pub use router::{router, routes, url};
 pub use ryde_macros::{RequestParts, StaticFiles};
@@ -59,7 +59,7 @@ impl IntoResponse for Html {
 }
 
 pub fn redirect(s: String) -> Response {
-    let headers = [(SET_COOKIE, format!("flash={}", "")), (LOCATION, s.into())];
+    let headers = [(SET_COOKIE, format!("flash={}", "")), (LOCATION, s)];
 
     (http::StatusCode::SEE_OTHER, headers).into_response()
 }
@@ -86,11 +86,17 @@ pub enum Error {
     DatabaseClose,
     Database(String),
     UniqueConstraintFailed(String),
+    ForeignKeyConstraintFailed,
+    NotNullConstraintFailed(String),
+    CheckConstraintFailed(String),
+    DatabaseBusy,
+    DatabaseReadOnly,
     Io(String),
     NotFound,
     InternalServer,
     Multipart(String),
     Join(String),
+    Migration(String),
 }
 
 impl std::fmt::Display for Error {
@@ -102,11 +108,23 @@ impl std::fmt::Display for Error {
             Error::UniqueConstraintFailed(e) => {
                 f.write_fmt(format_args!("Error: Unique constraint failed {}", e))
             }
+            Error::ForeignKeyConstraintFailed => {
+                f.write_str("Error: Foreign key constraint failed")
+            }
+            Error::NotNullConstraintFailed(e) => {
+                f.write_fmt(format_args!("Error: Not null constraint failed {}", e))
+            }
+            Error::CheckConstraintFailed(e) => {
+                f.write_fmt(format_args!("Error: Check constraint failed {}", e))
+            }
+            Error::DatabaseBusy => f.write_str("Error: Database is busy"),
+            Error::DatabaseReadOnly => f.write_str("Error: Database is read only"),
             Error::Io(e) => f.write_fmt(format_args!("Error: Io error {}", e)),
             Error::NotFound => f.write_str("Error: Not found"),
             Error::InternalServer => f.write_str("Error: Internal server error"),
             Error::Multipart(e) => f.write_fmt(format_args!("Error: {}", e)),
             Error::Join(x) => f.write_fmt(format_args!("{x}")),
+            Error::Migration(e) => f.write_fmt(format_args!("Error: Migration failed {}", e)),
         }
     }
 }
@@ -131,7 +149,12 @@ impl IntoResponse for Error {
             Error::DatabaseConnectionClosed => (500, "db connection closed".into()),
             Error::DatabaseClose => (500, "db closed".into()),
             Error::Database(err) => (500, err),
-            Error::UniqueConstraintFailed(columns) => (500, columns),
+            Error::UniqueConstraintFailed(columns) => (409, columns),
+            Error::ForeignKeyConstraintFailed => (422, "foreign key constraint failed".into()),
+            Error::NotNullConstraintFailed(column) => (422, column),
+            Error::CheckConstraintFailed(check) => (422, check),
+            Error::DatabaseBusy => (503, "database is busy".into()),
+            Error::DatabaseReadOnly => (503, "database is read only".into()),
             Error::Io(s) => (500, s),
             Error::NotFound => (404, "not found".into()),
             Error::InternalServer => (500, "internal server error".into()),
@@ -140,6 +163,7 @@ impl IntoResponse for Error {
                 "Unprocessable entity from multipart form request".into(),
             ),
             Error::Join(_) => (500, "internal server error".into()),
+            Error::Migration(_) => (500, "internal server error".into()),
         };
         Response::builder()
             .status(status)
@@ -164,17 +188,39 @@ impl From<rusqlite::Error> for Error {
     fn from(value: rusqlite::Error) -> Self {
         match value {
             rusqlite::Error::QueryReturnedNoRows => Error::NotFound,
-            err => {
-                // TODO: follow the white rabbit to the actual error for unique constraints
-                let s = err.to_string();
-                if s.starts_with("UNIQUE constraint failed: ") {
-                    Error::UniqueConstraintFailed(
-                        s.split(":").map(|s| s.trim()).last().unwrap_or("").into(),
-                    )
-                } else {
-                    Error::Database(s)
+            rusqlite::Error::SqliteFailure(err, ref message) => {
+                // "UNIQUE constraint failed: users.email" -> "users.email"
+                let detail = || {
+                    message
+                        .as_deref()
+                        .and_then(|message| message.split_once(": "))
+                        .map(|(_, detail)| detail.trim().to_string())
+                        .unwrap_or_default()
+                };
+                match (err.code, err.extended_code) {
+                    (
+                        _,
+                        rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+                        | rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY,
+                    ) => Error::UniqueConstraintFailed(detail()),
+                    (_, rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY) => {
+                        Error::ForeignKeyConstraintFailed
+                    }
+                    (_, rusqlite::ffi::SQLITE_CONSTRAINT_NOTNULL) => {
+                        Error::NotNullConstraintFailed(detail())
+                    }
+                    (_, rusqlite::ffi::SQLITE_CONSTRAINT_CHECK) => {
+                        Error::CheckConstraintFailed(detail())
+                    }
+                    (
+                        rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked,
+                        _,
+                    ) => Error::DatabaseBusy,
+                    (rusqlite::ErrorCode::ReadOnly, _) => Error::DatabaseReadOnly,
+                    _ => Error::Database(value.to_string()),
                 }
             }
+            err => Error::Database(err.to_string()),
         }
     }
 }